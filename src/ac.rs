@@ -0,0 +1,170 @@
+use anyhow::{Context, Result};
+use starship_battery::State;
+use std::path::Path;
+
+pub const POWER_SUPPLY_PATH: &str = "/sys/class/power_supply";
+
+// Battery states which indicate a connected power supply; "Not charging" shows up as `Unknown`
+pub fn state_on_ac(state: &State) -> bool {
+    matches!(state, State::Charging | State::Full | State::Unknown)
+}
+
+// Check the `online` attribute of all AC adapters, i.e. "Mains" and "USB" power supplies
+pub fn adapter_online(power_supply_path: &Path) -> Result<bool> {
+    let entries = std::fs::read_dir(power_supply_path)
+        .with_context(|| format!("Failed to read '{}'", power_supply_path.display()))?;
+    let mut found_adapter = false;
+    for entry in entries {
+        let path = entry?.path();
+        let supply_type = std::fs::read_to_string(path.join("type")).unwrap_or_default();
+        if !matches!(supply_type.trim(), "Mains" | "USB") {
+            continue;
+        }
+        found_adapter = true;
+        let online = std::fs::read_to_string(path.join("online"))
+            .with_context(|| format!("Failed to read '{}/online'", path.display()))?;
+        if online.trim() == "1" {
+            return Ok(true);
+        }
+    }
+    if !found_adapter {
+        return Err(anyhow::Error::msg(format!(
+            "Failed to find AC adapter in '{}'",
+            power_supply_path.display()
+        )));
+    }
+    Ok(false)
+}
+
+// Like `adapter_online`, but falls back to the battery state if the adapters can't be read
+pub fn adapter_or_state_on_ac(power_supply_path: &Path, state: &State) -> bool {
+    adapter_online(power_supply_path).unwrap_or_else(|e| {
+        warn!("{:#}; falling back to battery state", e);
+        state_on_ac(state)
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AcTransition {
+    Connected,
+    Disconnected,
+}
+
+// Keeps track of the power supply, reporting changes between readings
+#[derive(Debug, Default)]
+pub struct AcTracker {
+    on_ac: Option<bool>,
+}
+
+impl AcTracker {
+    pub fn update(&mut self, on_ac: bool) -> Option<AcTransition> {
+        let previous = self.on_ac.replace(on_ac);
+        match (previous, on_ac) {
+            (Some(false), true) => Some(AcTransition::Connected),
+            (Some(true), false) => Some(AcTransition::Disconnected),
+            _ => None, // First reading or no change
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn fake_power_supply(name: &str, supplies: &[(&str, &str, Option<&str>)]) -> PathBuf {
+        let root =
+            std::env::temp_dir().join(format!("battered-test-{}-{}", name, std::process::id()));
+        std::fs::remove_dir_all(&root).ok();
+        for (supply, supply_type, online) in supplies {
+            let dir = root.join(supply);
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("type"), format!("{}\n", supply_type)).unwrap();
+            if let Some(online) = online {
+                std::fs::write(dir.join("online"), format!("{}\n", online)).unwrap();
+            }
+        }
+        root
+    }
+
+    #[test]
+    fn test_state_on_ac() {
+        assert!(state_on_ac(&State::Charging));
+        assert!(state_on_ac(&State::Full));
+        assert!(state_on_ac(&State::Unknown));
+        assert!(!state_on_ac(&State::Discharging));
+        assert!(!state_on_ac(&State::Empty));
+    }
+
+    #[test]
+    fn test_tracker_transitions() {
+        let mut tracker = AcTracker::default();
+        assert_eq!(tracker.update(true), None); // Starting on AC is no transition
+        assert_eq!(tracker.update(true), None);
+        assert_eq!(tracker.update(false), Some(AcTransition::Disconnected));
+        assert_eq!(tracker.update(false), None);
+        assert_eq!(tracker.update(true), Some(AcTransition::Connected));
+    }
+
+    #[test]
+    fn test_adapter_online() {
+        let root = fake_power_supply(
+            "online",
+            &[("BAT0", "Battery", None), ("AC", "Mains", Some("1"))],
+        );
+        let result = adapter_online(&root);
+        std::fs::remove_dir_all(&root).ok();
+        assert!(result.unwrap());
+    }
+
+    #[test]
+    fn test_adapter_offline() {
+        let root = fake_power_supply(
+            "offline",
+            &[
+                ("BAT0", "Battery", None),
+                ("AC", "Mains", Some("0")),
+                ("ucsi-source-psy-USBC000:001", "USB", Some("0")),
+            ],
+        );
+        let result = adapter_online(&root);
+        std::fs::remove_dir_all(&root).ok();
+        assert!(!result.unwrap());
+    }
+
+    #[test]
+    fn test_usb_adapter_online() {
+        let root = fake_power_supply(
+            "usb",
+            &[
+                ("AC", "Mains", Some("0")),
+                ("ucsi-source-psy-USBC000:001", "USB", Some("1")),
+            ],
+        );
+        let result = adapter_online(&root);
+        std::fs::remove_dir_all(&root).ok();
+        assert!(result.unwrap());
+    }
+
+    #[test]
+    fn test_no_adapter() {
+        let root = fake_power_supply("none", &[("BAT0", "Battery", None)]);
+        let result = adapter_online(&root);
+        std::fs::remove_dir_all(&root).ok();
+        assert!(result.is_err());
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .starts_with("Failed to find AC adapter"));
+    }
+
+    #[test]
+    fn test_fall_back_to_battery_state() {
+        let root = fake_power_supply("fallback", &[("BAT0", "Battery", None)]);
+        let charging = adapter_or_state_on_ac(&root, &State::Charging);
+        let discharging = adapter_or_state_on_ac(&root, &State::Discharging);
+        std::fs::remove_dir_all(&root).ok();
+        assert!(charging);
+        assert!(!discharging);
+    }
+}
@@ -0,0 +1,21 @@
+use chrono::{Local, NaiveDateTime};
+use std::time::Instant;
+
+pub trait Clock {
+    // Monotonic time for measuring durations
+    fn now(&self) -> Instant;
+    // Wall clock time for schedules
+    fn local_now(&self) -> NaiveDateTime;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn local_now(&self) -> NaiveDateTime {
+        Local::now().naive_local()
+    }
+}
@@ -1,10 +1,59 @@
+use crate::expr::Expression;
+use crate::process::CommandState;
+use anyhow::{bail, Result};
+use chrono::{Datelike, NaiveDateTime, NaiveTime, Weekday};
 use notify_rust::{Timeout, Urgency};
 use serde::de::Error as SerdeError;
 use serde::{Deserialize, Deserializer};
 use serde_with::{serde_as, DurationSeconds};
 use shell_words::split as shell_split;
+use std::collections::BTreeMap;
+use std::fmt;
 use std::path::PathBuf;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use toml::{Table, Value};
+
+// Shell running commands with `shell = true`
+const SHELL: &str = "/bin/sh";
+
+// Sections which inherit from `[defaults.action]`
+const ACTION_SECTIONS: &[&str] = &["action", "trigger", "on_ac"];
+// Sections which only inherit the settings of `Action` from `[defaults.action]`
+const OTHER_SECTIONS: &[&str] = &["power_draw", "drop", "temperature", "health"];
+// Settings of `Action`, shared by all sections
+const ACTION_KEYS: &[&str] = &[
+    "name",
+    "tags",
+    "enabled",
+    "active_hours",
+    "days",
+    "when",
+    "command",
+    "shell",
+    "cwd",
+    "env",
+    "commands",
+    "mode",
+    "stop_on_failure",
+    "system",
+    "system_fallback",
+    "timeout",
+    "on_failure",
+    "retries",
+    "retry_delay",
+    "notify_output",
+    "notify",
+];
+// Sections whose `notify` tables inherit from `[defaults.notify]`
+const NOTIFY_SECTIONS: &[&str] = &[
+    "action",
+    "trigger",
+    "on_ac",
+    "power_draw",
+    "drop",
+    "temperature",
+    "health",
+];
 
 #[serde_as]
 #[derive(Debug, Deserialize)]
@@ -12,31 +61,407 @@ pub struct Config {
     #[serde_as(as = "DurationSeconds<u64>")]
     #[serde(default = "default_interval")]
     pub interval: Duration,
-    pub action: Vec<Action>,
-    pub on_ac: Option<OnAcAction>,
+    // Aliases for triggers on "below" and "ac_connected"
+    #[serde(default, deserialize_with = "deserialize_triggers")]
+    pub action: Vec<Trigger>,
+    #[serde(default, deserialize_with = "deserialize_on_ac")]
+    pub on_ac: Option<Trigger>,
+    #[serde(default, deserialize_with = "deserialize_triggers")]
+    pub trigger: Vec<Trigger>,
+    #[serde(default)]
+    pub power_draw: Vec<PowerDrawAction>,
+    #[serde(default)]
+    pub drop: Vec<DropAction>,
+    #[serde(default)]
+    pub temperature: Vec<TemperatureAction>,
+    #[serde(default, deserialize_with = "deserialize_health")]
+    pub health: Vec<HealthAction>,
     #[serde(default, deserialize_with = "deserialize_serial_number")]
     pub serial_number: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_optional_float_percentage")]
+    pub hysteresis: Option<f32>,
+    #[serde(default)]
+    pub ac_source: AcSource,
+    #[serde(default = "default_exclusive")]
+    pub exclusive: bool,
+    // Changes of battery state and power supply only count once they held this long
+    #[serde_as(as = "Option<DurationSeconds<u64>>")]
+    #[serde(default)]
+    pub debounce: Option<Duration>,
+    #[serde(default)]
+    pub debounce_readings: Option<u32>,
+    #[serde(default)]
+    pub on_failure: OnFailure,
 }
 
-#[derive(Debug, Deserialize)]
+impl Config {
+    /// Commands and notifications of all triggers, whatever their kind.
+    pub fn actions_mut(&mut self) -> impl Iterator<Item = &mut Action> {
+        let triggers = self.trigger.iter_mut().chain(&mut self.action);
+        triggers
+            .chain(&mut self.on_ac)
+            .map(|trigger| &mut trigger.action)
+            .chain(self.power_draw.iter_mut().map(|a| &mut a.action))
+            .chain(self.drop.iter_mut().map(|a| &mut a.action))
+            .chain(self.temperature.iter_mut().map(|a| &mut a.action))
+            .chain(self.health.iter_mut().map(|a| &mut a.action))
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AcSource {
+    // Derive power supply connection from battery state
+    #[default]
+    Battery,
+    // Read `online` attribute of AC adapters
+    Adapter,
+}
+
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OnFailure {
+    // Stop battered
+    Exit,
+    // Keep going as if the command succeeded
+    #[default]
+    Continue,
+    // Don't take the action again
+    DisableAction,
+    // Run the command again, see `retries`
+    Retry,
+}
+
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Event {
+    // Battery level dropped below `percentage`
+    #[default]
+    Below,
+    // Battery level rose above `percentage`
+    Above,
+    AcConnected,
+    AcDisconnected,
+    // Battery became fully charged
+    Full,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Event::Below => "below",
+            Event::Above => "above",
+            Event::AcConnected => "ac_connected",
+            Event::AcDisconnected => "ac_disconnected",
+            Event::Full => "full",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Settings shared by all kinds of triggers: name, conditions, commands and notification
+#[serde_as]
+#[derive(Debug, Default, Deserialize)]
 pub struct Action {
-    #[serde(deserialize_with = "deserialize_float_percentage")]
-    pub percentage: f32,
+    pub name: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub enabled: Option<bool>,
+    #[serde(default, deserialize_with = "deserialize_active_hours")]
+    pub active_hours: Option<ActiveHours>,
+    #[serde(default, deserialize_with = "deserialize_days")]
+    pub days: Vec<Weekday>,
+    #[serde(default, deserialize_with = "deserialize_expression")]
+    pub when: Option<Expression>,
     #[serde(default, deserialize_with = "deserialize_command")]
-    pub command: Option<Vec<String>>,
+    pub command: Option<Command>,
+    #[serde(default)]
+    pub shell: bool,
+    pub cwd: Option<PathBuf>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    #[serde(default, deserialize_with = "deserialize_commands")]
+    pub commands: Vec<Command>,
+    #[serde(default)]
+    pub mode: CommandMode,
+    #[serde(default)]
+    pub stop_on_failure: bool,
+    pub system: Option<SystemAction>,
+    pub system_fallback: Option<SystemAction>,
+    #[serde_as(as = "Option<DurationSeconds<u64>>")]
+    #[serde(default)]
+    pub timeout: Option<Duration>,
+    pub on_failure: Option<OnFailure>,
+    pub retries: Option<u32>,
+    #[serde_as(as = "Option<DurationSeconds<u64>>")]
+    #[serde(default)]
+    pub retry_delay: Option<Duration>,
+    #[serde(default)]
+    pub notify_output: bool,
     pub notify: Option<Notify>,
+    #[serde(skip)]
+    pub command_state: CommandState,
+    #[serde(skip)]
+    pub toggled: Option<bool>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
-pub struct OnAcAction {
+impl Action {
+    /// Name and tags, which can be used to enable or disable the action.
+    pub fn selectors(&self) -> impl Iterator<Item = &str> + Clone {
+        self.name.iter().chain(self.tags.iter()).map(String::as_str)
+    }
+
+    /// Actions disabled after a failed command stay disabled until battered restarts.
+    pub fn is_enabled(&self) -> bool {
+        self.toggled.or(self.enabled).unwrap_or(true) && !self.command_state.disabled
+    }
+}
+
+#[serde_as]
+#[derive(Debug, Default, Deserialize)]
+pub struct Trigger {
+    #[serde(default)]
+    pub on: Event,
+    #[serde(default, deserialize_with = "deserialize_optional_float_percentage")]
+    pub percentage: Option<f32>,
+    #[serde(default, deserialize_with = "deserialize_energy")]
+    pub energy_below: Option<f32>,
+    #[serde(default)]
+    pub capacity: Capacity,
+    #[serde(flatten)]
+    pub action: Action,
+    #[serde(default, deserialize_with = "deserialize_optional_float_percentage")]
+    pub hysteresis: Option<f32>,
+    pub exclusive: Option<bool>,
+    #[serde_as(as = "Option<DurationSeconds<u64>>")]
+    #[serde(default)]
+    pub repeat: Option<Duration>,
+    pub repeat_limit: Option<u32>,
+    #[serde(default)]
+    pub repeat_command: bool,
+    #[serde_as(as = "Option<DurationSeconds<u64>>")]
+    #[serde(default)]
+    pub countdown: Option<Duration>,
+    #[serde_as(as = "DurationSeconds<u64>")]
+    #[serde(default = "default_countdown_interval")]
+    pub countdown_interval: Duration,
+    #[serde(skip)]
+    pub fired: bool,
+    #[serde(skip)]
+    pub last_taken: Option<Instant>,
+    #[serde(skip)]
+    pub repeat_count: u32,
+    #[serde(skip)]
+    pub countdown_deadline: Option<Instant>,
+    #[serde(skip)]
+    pub notification_id: Option<u32>,
+    #[serde(skip)]
+    pub threshold: Option<f32>,
+}
+
+impl Trigger {
+    /// Threshold as battery level, i.e. relative to the current full energy.
+    pub fn threshold(&self) -> Option<f32> {
+        self.threshold.or(self.percentage)
+    }
+
+    /// Convert energy and design capacity thresholds into battery levels.
+    pub fn resolve_threshold(&mut self, energy_full: f32, energy_full_design: f32) {
+        if energy_full <= 0.0 {
+            self.threshold = None;
+            return;
+        }
+        // Battery level is energy relative to full energy, so comparing levels compares energy
+        self.threshold = match (self.energy_below, self.capacity) {
+            (Some(energy), _) => Some(energy / energy_full),
+            (None, Capacity::Design) => self
+                .percentage
+                .map(|percentage| percentage * energy_full_design / energy_full),
+            (None, Capacity::Full) => None,
+        };
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Capacity {
+    // Percentages relate to the energy of the fully charged battery
+    #[default]
+    Full,
+    // Percentages relate to the design energy of the battery
+    Design,
+}
+
+// How to run several commands of an action
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CommandMode {
+    // One after the other, in the given order
+    #[default]
+    Sequential,
+    // All at once
+    Parallel,
+}
+
+// Built-in action taken through logind
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SystemAction {
+    Suspend,
+    Hibernate,
+    // Suspend, but resume from disk if the battery runs empty
+    HybridSleep,
+    Poweroff,
+    // Lock the session of the user
+    Lock,
+}
+
+impl fmt::Display for SystemAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            SystemAction::Suspend => "suspend",
+            SystemAction::Hibernate => "hibernate",
+            SystemAction::HybridSleep => "hybrid-sleep",
+            SystemAction::Poweroff => "poweroff",
+            SystemAction::Lock => "lock",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Command given as a string, split like a shell would, or as an array of arguments
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Line(String),
+    Args(Vec<String>),
+}
+
+impl Command {
+    // Arguments to run, through the shell with `shell = true`
+    // Placeholders are filled in by `fill`, but not within shell code, so values can't inject commands
+    pub fn args(&self, shell: bool, fill: impl Fn(&str) -> String) -> Vec<String> {
+        match (self, shell) {
+            (Command::Line(line), false) => shell_split(line)
+                .unwrap_or_default() // Checked when parsing the config
+                .iter()
+                .map(|arg| fill(arg))
+                .collect(),
+            (Command::Args(args), false) => args.iter().map(|arg| fill(arg)).collect(),
+            (Command::Line(line), true) => vec![SHELL.into(), "-c".into(), line.clone()],
+            // Further arguments are passed to the shell code as `$1`, `$2`, ...
+            (Command::Args(args), true) => [SHELL, "-c", &args[0], "sh"]
+                .into_iter()
+                .map(String::from)
+                .chain(args[1..].iter().map(|arg| fill(arg)))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ActiveHours {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl ActiveHours {
+    pub fn contains(&self, time: &NaiveTime) -> bool {
+        if self.start < self.end {
+            &self.start <= time && time < &self.end
+        } else {
+            // Window spans midnight
+            &self.start <= time || time < &self.end
+        }
+    }
+}
+
+// Check whether `now` is within the given hours and days; unset conditions always apply
+pub fn is_scheduled(
+    active_hours: &Option<ActiveHours>,
+    days: &[Weekday],
+    now: &NaiveDateTime,
+) -> bool {
+    let within_hours = active_hours.is_none_or(|hours| hours.contains(&now.time()));
+    let within_days = days.is_empty() || days.contains(&now.weekday());
+    within_hours && within_days
+}
+
+#[serde_as]
+#[derive(Debug, Deserialize)]
+pub struct PowerDrawAction {
+    #[serde(deserialize_with = "deserialize_positive_float")]
+    pub watts: f32,
+    #[serde_as(as = "DurationSeconds<u64>")]
+    #[serde(default = "default_power_draw_duration")]
+    pub duration: Duration,
+    #[serde(flatten)]
+    pub action: Action,
+    #[serde(skip)]
+    pub above_since: Option<Instant>,
+    #[serde(skip)]
+    pub fired: bool,
+}
+
+impl PowerDrawAction {
+    /// Forget about any previously observed high power draw.
+    pub fn reset(&mut self) {
+        self.above_since = None;
+        self.fired = false;
+    }
+}
+
+#[serde_as]
+#[derive(Debug, Deserialize)]
+pub struct DropAction {
+    #[serde(deserialize_with = "deserialize_float_percentage")]
+    pub by: f32,
+    #[serde_as(as = "DurationSeconds<u64>")]
+    #[serde(default = "default_drop_within")]
+    pub within: Duration,
+    #[serde(flatten)]
+    pub action: Action,
+    #[serde(skip)]
+    pub fired: bool,
+}
+
+#[serde_as]
+#[derive(Debug, Deserialize)]
+pub struct TemperatureAction {
+    pub above: f32,
     #[serde(
-        default = "default_ac_percentage",
-        deserialize_with = "deserialize_float_percentage"
+        default = "default_temperature_hysteresis",
+        deserialize_with = "deserialize_non_negative_float"
     )]
-    pub percentage: f32,
-    #[serde(default, deserialize_with = "deserialize_command")]
-    pub command: Option<Vec<String>>,
-    pub notify: Option<Notify>,
+    pub hysteresis: f32,
+    #[serde(flatten)]
+    pub action: Action,
+    #[serde(skip)]
+    pub fired: bool,
+}
+
+impl TemperatureAction {
+    /// Arm action again once the temperature has cooled down far enough.
+    pub fn rearm(&mut self, temperature: &f32) {
+        if *temperature < self.above - self.hysteresis {
+            self.fired = false;
+        }
+    }
+}
+
+#[serde_as]
+#[derive(Debug, Deserialize)]
+pub struct HealthAction {
+    #[serde(default, deserialize_with = "deserialize_optional_float_percentage")]
+    pub below: Option<f32>,
+    pub cycle_count: Option<u32>,
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+impl HealthAction {
+    pub fn exceeds_cycle_count(&self, cycle_count: &u32) -> bool {
+        self.cycle_count.is_some_and(|limit| cycle_count > &limit)
+    }
 }
 
 #[serde_as]
@@ -65,8 +490,98 @@ fn default_interval() -> Duration {
     Duration::from_secs(60)
 }
 
-fn default_ac_percentage() -> f32 {
-    0.0
+fn default_power_draw_duration() -> Duration {
+    Duration::from_secs(300)
+}
+
+fn default_exclusive() -> bool {
+    true
+}
+
+fn default_drop_within() -> Duration {
+    Duration::from_secs(600)
+}
+
+fn default_countdown_interval() -> Duration {
+    Duration::from_secs(5)
+}
+
+fn default_temperature_hysteresis() -> f32 {
+    2.0
+}
+
+fn deserialize_triggers<'de, D>(deserializer: D) -> Result<Vec<Trigger>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let triggers: Vec<Trigger> = Deserialize::deserialize(deserializer)?;
+    // Thresholds are required for battery level triggers
+    for trigger in &triggers {
+        let message = match (trigger.on, trigger.percentage, trigger.energy_below) {
+            (Event::Below, None, None) => "missing field `percentage` or `energy_below`",
+            (Event::Above, None, _) => "missing field `percentage`",
+            (Event::Below, Some(_), Some(_)) => {
+                "`percentage` and `energy_below` are mutually exclusive"
+            }
+            (Event::Below, _, _) => continue,
+            (_, _, Some(_)) => "`energy_below` is not supported",
+            _ => continue,
+        };
+        return Err(D::Error::custom(format!(
+            "{} for trigger on '{}'",
+            message, trigger.on
+        )));
+    }
+    Ok(triggers)
+}
+
+fn deserialize_health<'de, D>(deserializer: D) -> Result<Vec<HealthAction>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let health: Vec<HealthAction> = Deserialize::deserialize(deserializer)?;
+    // Health actions without a limit would never be taken
+    if let Some(i) = health
+        .iter()
+        .position(|action| action.below.is_none() && action.cycle_count.is_none())
+    {
+        return Err(D::Error::custom(format!(
+            "missing field `below` or `cycle_count` in [[health]] #{}",
+            i + 1
+        )));
+    }
+    Ok(health)
+}
+
+fn deserialize_on_ac<'de, D>(deserializer: D) -> Result<Option<Trigger>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let on_ac: Option<Trigger> = Deserialize::deserialize(deserializer)?;
+    Ok(on_ac.map(|on_ac| Trigger {
+        on: Event::AcConnected,
+        ..on_ac
+    }))
+}
+
+fn deserialize_energy<'de, D>(deserializer: D) -> Result<Option<f32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    // Deserialize the string
+    let value: String = String::deserialize(deserializer)?;
+    // Attempt to parse the `<number> Wh` energy
+    let energy = value
+        .trim()
+        .strip_suffix("Wh")
+        .and_then(|energy| energy.trim().parse::<f32>().ok());
+    match energy {
+        Some(energy) if energy > 0.0 => Ok(Some(energy)),
+        _ => Err(D::Error::custom(format!(
+            "Failed to parse energy '{}': expected format '<number> Wh'",
+            value
+        ))),
+    }
 }
 
 fn deserialize_float_percentage<'de, D>(deserializer: D) -> Result<f32, D::Error>
@@ -82,18 +597,149 @@ where
     Ok(value)
 }
 
-fn deserialize_command<'de, D>(deserializer: D) -> Result<Option<Vec<String>>, D::Error>
+fn deserialize_optional_float_percentage<'de, D>(deserializer: D) -> Result<Option<f32>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    deserialize_float_percentage(deserializer).map(Some)
+}
+
+fn deserialize_positive_float<'de, D>(deserializer: D) -> Result<f32, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    // Deserialize float
+    let value: f32 = Deserialize::deserialize(deserializer)?;
+    // Check valid range
+    if value <= 0.0 {
+        return Err(D::Error::custom("value must be greater than 0"));
+    }
+    Ok(value)
+}
+
+fn deserialize_non_negative_float<'de, D>(deserializer: D) -> Result<f32, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    // Deserialize float
+    let value: f32 = Deserialize::deserialize(deserializer)?;
+    // Check valid range
+    if value < 0.0 {
+        return Err(D::Error::custom("value must not be negative"));
+    }
+    Ok(value)
+}
+
+// Command as written in the config, see `Command`
+#[derive(Deserialize)]
+#[serde(untagged, expecting = "a string or an array of strings")]
+enum CommandValue {
+    Line(String),
+    Args(Vec<String>),
+}
+
+impl CommandValue {
+    fn into_command<E: SerdeError>(self) -> Result<Command, E> {
+        match self {
+            // Attempt to split the command, so that it can be run later on
+            CommandValue::Line(line) => match shell_split(&line) {
+                Ok(args) if !args.is_empty() => Ok(Command::Line(line)),
+                Ok(_) => Err(E::custom("Command must not be empty")),
+                Err(e) => Err(E::custom(format!("Failed to split command: {}", e))),
+            },
+            CommandValue::Args(args) if !args.is_empty() => Ok(Command::Args(args)),
+            CommandValue::Args(_) => Err(E::custom("Command must not be empty")),
+        }
+    }
+}
+
+fn deserialize_command<'de, D>(deserializer: D) -> Result<Option<Command>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    CommandValue::deserialize(deserializer)?
+        .into_command()
+        .map(Some)
+}
+
+fn deserialize_commands<'de, D>(deserializer: D) -> Result<Vec<Command>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<CommandValue>::deserialize(deserializer)?
+        .into_iter()
+        .map(CommandValue::into_command)
+        .collect()
+}
+
+fn deserialize_active_hours<'de, D>(deserializer: D) -> Result<Option<ActiveHours>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    // Deserialize the string
+    let value: String = String::deserialize(deserializer)?;
+    // Attempt to parse the `HH:MM-HH:MM` time range
+    let parse_time = |time: &str| NaiveTime::parse_from_str(time.trim(), "%H:%M");
+    match value.split_once('-') {
+        Some((start, end)) => match (parse_time(start), parse_time(end)) {
+            (Ok(start), Ok(end)) => Ok(Some(ActiveHours { start, end })),
+            _ => Err(D::Error::custom(format!(
+                "Failed to parse active hours '{}': expected format 'HH:MM-HH:MM'",
+                value
+            ))),
+        },
+        None => Err(D::Error::custom(format!(
+            "Failed to parse active hours '{}': expected format 'HH:MM-HH:MM'",
+            value
+        ))),
+    }
+}
+
+fn deserialize_days<'de, D>(deserializer: D) -> Result<Vec<Weekday>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    // Deserialize the list of strings
+    let values: Vec<String> = Vec::deserialize(deserializer)?;
+    let parse_day = |day: &str| {
+        day.trim()
+            .parse::<Weekday>()
+            .map_err(|_| D::Error::custom(format!("Failed to parse day '{}'", day)))
+    };
+    // Expand day names and ranges like `mon-fri`
+    let mut days = Vec::new();
+    for value in values {
+        match value.split_once('-') {
+            Some((first, last)) => {
+                let (mut day, last) = (parse_day(first)?, parse_day(last)?);
+                days.push(day);
+                while day != last {
+                    day = day.succ();
+                    days.push(day);
+                }
+            }
+            None => days.push(parse_day(&value)?),
+        }
+    }
+    Ok(days)
+}
+
+fn deserialize_expression<'de, D>(deserializer: D) -> Result<Option<Expression>, D::Error>
 where
     D: Deserializer<'de>,
 {
     // Deserialize the string
     let value: String = String::deserialize(deserializer)?;
-    // Attempt to split the command
-    match shell_split(&value) {
-        Ok(command) => Ok(Some(command)),
-        Err(e) => Err(D::Error::custom(format!("Failed to split command: {}", e))),
+    // Attempt to parse and type check the expression
+    match Expression::parse(&value) {
+        Ok(expression) => Ok(Some(expression)),
+        Err(e) => Err(D::Error::custom(format!(
+            "Failed to parse expression '{}': {}",
+            value, e
+        ))),
     }
 }
+
 fn deserialize_urgency<'de, D>(deserializer: D) -> Result<Urgency, D::Error>
 where
     D: Deserializer<'de>,
@@ -130,6 +776,123 @@ where
     Ok(Some(value))
 }
 
+/// Parse config, resolving `[defaults]` into the sections inheriting from them.
+pub fn parse_config(config_values: &str) -> Result<Config> {
+    let mut table: Table = toml::from_str(config_values)?;
+    check_legacy_events(&mut table)?;
+    let mut config: Config = match table.remove("defaults") {
+        Some(defaults) => {
+            apply_defaults(&mut table, defaults)?;
+            Value::Table(table).try_into()?
+        }
+        None => toml::from_str(config_values)?, // Keep line numbers in errors
+    };
+    // battered would run but never do anything
+    if config.actions_mut().next().is_none() {
+        bail!("At least one action of any kind, e.g. [[action]] or [[trigger]], is required");
+    }
+    Ok(config)
+}
+
+fn apply_defaults(table: &mut Table, defaults: Value) -> Result<()> {
+    let Value::Table(mut defaults) = defaults else {
+        bail!("[defaults] must be a table");
+    };
+    let mut take_section = |name: &str| match defaults.remove(name) {
+        Some(Value::Table(section)) => Ok(section),
+        Some(_) => bail!("[defaults.{}] must be a table", name),
+        None => Ok(Table::new()),
+    };
+    let action_defaults = take_section("action")?;
+    let notify_defaults = take_section("notify")?;
+    if let Some(name) = defaults.keys().next() {
+        bail!("Unknown section [defaults.{}]", name);
+    }
+    // Would silently move every action to another event
+    if action_defaults.contains_key("on") {
+        bail!("`on` can't be set in [defaults.action]");
+    }
+
+    for name in ACTION_SECTIONS {
+        for (_, section) in sections_mut(table, name) {
+            merge_missing(section, &action_defaults);
+        }
+    }
+    // Other settings may mean something else there, e.g. `hysteresis` of temperature actions
+    let shared_defaults: Table = action_defaults
+        .into_iter()
+        .filter(|(key, _)| ACTION_KEYS.contains(&key.as_str()))
+        .collect();
+    for name in OTHER_SECTIONS {
+        for (_, section) in sections_mut(table, name) {
+            merge_missing(section, &shared_defaults);
+        }
+    }
+    for name in NOTIFY_SECTIONS {
+        for (label, section) in sections_mut(table, name) {
+            let Some(Value::Table(notify)) = section.get_mut("notify") else {
+                continue;
+            };
+            merge_missing(notify, &notify_defaults);
+            if !notify.contains_key("summary") {
+                bail!(
+                    "missing field `summary` in notify of {}, and not set in [defaults.notify]",
+                    label
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+// `[[action]]` and `[on_ac]` stand for triggers on a fixed event, which `on` can't change
+fn check_legacy_events(table: &mut Table) -> Result<()> {
+    for (name, event) in [("action", "below"), ("on_ac", "ac_connected")] {
+        for (label, section) in sections_mut(table, name) {
+            if let Some(Value::String(on)) = section.get("on") {
+                if on != event {
+                    bail!(
+                        "`on = \"{}\"` conflicts with {}, which always triggers on '{}'; use [[trigger]] instead",
+                        on,
+                        label,
+                        event
+                    );
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// Tables of a section, labelled for error messages
+fn sections_mut<'t>(table: &'t mut Table, name: &str) -> Vec<(String, &'t mut Table)> {
+    match table.get_mut(name) {
+        Some(Value::Table(section)) => vec![(format!("[{}]", name), section)],
+        Some(Value::Array(sections)) => sections
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(i, section)| match section {
+                Value::Table(section) => Some((format!("[[{}]] #{}", name, i + 1), section)),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+// Copy values from `defaults` which are not set in `table`, merging sub-tables
+fn merge_missing(table: &mut Table, defaults: &Table) {
+    for (key, default) in defaults {
+        match (table.get_mut(key), default) {
+            (None, _) => {
+                table.insert(key.clone(), default.clone());
+            }
+            (Some(Value::Table(value)), Value::Table(default)) => merge_missing(value, default),
+            _ => (),
+        }
+    }
+}
+
 // Taken from i3status-rust
 pub fn xdg_config_home() -> PathBuf {
     // In the unlikely event that $HOME is not set, it doesn't really matter
@@ -141,12 +904,19 @@ pub fn xdg_config_home() -> PathBuf {
     PathBuf::from(&config_path)
 }
 
+pub fn xdg_state_home() -> PathBuf {
+    let state_path = std::env::var("XDG_STATE_HOME").unwrap_or(format!(
+        "{}/.local/state",
+        std::env::var("HOME").unwrap_or_else(|_| "".to_string())
+    ));
+    PathBuf::from(&state_path)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::env;
     use std::sync::Mutex;
-    use toml;
 
     static ENV_VAR_MUTEX: Mutex<()> = Mutex::new(());
 
@@ -169,16 +939,21 @@ mod tests {
         let config: Config = toml::from_str(toml_str).unwrap();
         assert_eq!(config.interval, Duration::from_secs(120));
         assert_eq!(config.serial_number, Some("31415".to_string()));
-        assert_eq!(config.action[0].percentage, 0.84);
+        assert_eq!(config.action[0].percentage, Some(0.84));
         assert_eq!(
-            config.action[0].command,
-            Some(vec![
+            config.action[0]
+                .action
+                .command
+                .as_ref()
+                .unwrap()
+                .args(false, str::to_string),
+            vec![
                 "./powersave.sh".to_string(),
                 "profile".to_string(),
                 "laptop-battery-powersave".to_string()
-            ])
+            ]
         );
-        let notify = config.action[0].notify.as_ref().unwrap();
+        let notify = config.action[0].action.notify.as_ref().unwrap();
         assert_eq!(notify.summary, "Battery discharging");
         assert_eq!(notify.urgency, Urgency::Low);
         assert_eq!(notify.icon, "battery-discharging");
@@ -197,6 +972,32 @@ mod tests {
         let config: Config = toml::from_str(toml_str).unwrap();
         assert_eq!(config.interval, Duration::from_secs(60));
         assert_eq!(config.serial_number, None);
+        assert!(config.power_draw.is_empty());
+        assert!(config.drop.is_empty());
+        assert!(config.temperature.is_empty());
+        assert!(config.health.is_empty());
+        assert_eq!(config.hysteresis, None);
+        assert_eq!(config.ac_source, AcSource::Battery);
+        assert!(config.exclusive);
+        assert_eq!(config.debounce, None);
+        assert_eq!(config.debounce_readings, None);
+        assert_eq!(config.on_failure, OnFailure::Continue);
+        assert_eq!(config.action[0].exclusive, None);
+        assert_eq!(config.action[0].action.name, None);
+        assert!(config.action[0].action.tags.is_empty());
+        assert_eq!(config.action[0].action.enabled, None);
+        assert_eq!(config.action[0].hysteresis, None);
+        assert_eq!(config.action[0].repeat, None);
+        assert_eq!(config.action[0].repeat_limit, None);
+        assert!(!config.action[0].repeat_command);
+        assert_eq!(config.action[0].countdown, None);
+        assert_eq!(config.action[0].action.retries, None);
+        assert_eq!(config.action[0].action.retry_delay, None);
+        assert!(!config.action[0].action.notify_output);
+        assert_eq!(config.action[0].countdown_interval, Duration::from_secs(5));
+        assert_eq!(config.action[0].action.active_hours, None);
+        assert!(config.action[0].action.days.is_empty());
+        assert!(config.action[0].action.when.is_none());
     }
 
     #[test]
@@ -212,7 +1013,7 @@ mod tests {
         "#; // `0` means no timeout
 
         let config: Config = toml::from_str(toml_str).unwrap();
-        let notify = config.action[0].notify.as_ref().unwrap();
+        let notify = config.action[0].action.notify.as_ref().unwrap();
         assert_eq!(notify.timeout, Timeout::Never);
     }
 
@@ -221,30 +1022,147 @@ mod tests {
         let toml_str = r#"
         "#;
 
-        let result: Result<Config, toml::de::Error> = toml::from_str(toml_str);
-        assert!(result.is_err());
-        assert_eq!(result.unwrap_err().message(), "missing field `action`");
+        let result = parse_config(toml_str);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "At least one action of any kind, e.g. [[action]] or [[trigger]], is required"
+        );
+
+        let toml_str = r#"
+        [defaults.notify]
+        summary = "Battery low"
+        "#;
+        assert!(parse_config(toml_str).is_err());
     }
 
     #[test]
-    fn test_invalid_interval_type() {
+    fn test_valid_triggers() {
         let toml_str = r#"
-        # Interval has to be valid Duration
-        interval = "not_a_number"
+        [[trigger]]
+        on = "above"
+        percentage = 0.8
+        [trigger.notify]
+        summary = "Battery at $percentage%, unplug now"
+
+        [[trigger]]
+        on = "ac_disconnected"
+        command = "./powersave.sh profile battery"
+
+        [[trigger]]
+        on = "full"
 
         [[action]]
-        percentage = 0.99
+        percentage = 0.1
+
+        [on_ac]
+        percentage = 0.5
         "#;
 
-        let result: Result<Config, toml::de::Error> = toml::from_str(toml_str);
-        assert!(result.is_err());
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.trigger[0].on, Event::Above);
+        assert_eq!(config.trigger[0].percentage, Some(0.8));
+        assert_eq!(config.trigger[1].on, Event::AcDisconnected);
+        assert_eq!(config.trigger[1].percentage, None);
+        assert_eq!(config.trigger[2].on, Event::Full);
+        // Aliases
+        assert_eq!(config.action[0].on, Event::Below);
+        assert_eq!(config.on_ac.as_ref().unwrap().on, Event::AcConnected);
+        assert_eq!(config.on_ac.unwrap().percentage, Some(0.5));
     }
 
     #[test]
-    fn test_invalid_percentage_values() {
-        let test_values = vec![
+    fn test_invalid_triggers() {
+        let test_values = [
+            (
+                r#"
+                [[trigger]]
+                on = "below"
+                "#,
+                "missing field `percentage` or `energy_below` for trigger on 'below'",
+            ),
+            (
+                r#"
+                [[trigger]]
+                on = "above"
+                command = "true"
+                "#,
+                "missing field `percentage` for trigger on 'above'",
+            ),
+            (
+                r#"
+                [[action]]
+                command = "true"
+                "#,
+                "missing field `percentage` or `energy_below` for trigger on 'below'",
+            ),
+            (
+                r#"
+                [[action]]
+                percentage = 0.1
+                energy_below = "8 Wh"
+                "#,
+                "`percentage` and `energy_below` are mutually exclusive for trigger on 'below'",
+            ),
+            (
+                r#"
+                [[trigger]]
+                on = "ac_connected"
+                energy_below = "8 Wh"
+                "#,
+                "`energy_below` is not supported for trigger on 'ac_connected'",
+            ),
+            (
+                r#"
+                [[action]]
+                energy_below = "8 kWh"
+                "#,
+                "Failed to parse energy '8 kWh': expected format '<number> Wh'",
+            ),
+            (
+                r#"
+                [[action]]
+                energy_below = "0 Wh"
+                "#,
+                "Failed to parse energy '0 Wh': expected format '<number> Wh'",
+            ),
+        ];
+
+        for (value, message) in test_values {
+            let result: Result<Config, toml::de::Error> = toml::from_str(value);
+            assert_eq!(result.unwrap_err().message(), message);
+        }
+
+        let result: Result<Config, toml::de::Error> = toml::from_str(
             r#"
-            [[action]]
+            [[trigger]]
+            on = "sideways"
+            "#,
+        );
+        assert!(result
+            .unwrap_err()
+            .message()
+            .starts_with("unknown variant `sideways`"));
+    }
+
+    #[test]
+    fn test_invalid_interval_type() {
+        let toml_str = r#"
+        # Interval has to be valid Duration
+        interval = "not_a_number"
+
+        [[action]]
+        percentage = 0.99
+        "#;
+
+        let result: Result<Config, toml::de::Error> = toml::from_str(toml_str);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_invalid_percentage_values() {
+        let test_values = [
+            r#"
+            [[action]]
             percentage = -0.2
             "#,
             r#"
@@ -350,7 +1268,7 @@ mod tests {
         timeout = -5
         "#; // Negative timeout is silently converted to `Timeout::Default` by notify-rust
         let config: Config = toml::from_str(toml_str).unwrap();
-        let notify = config.action[0].notify.as_ref().unwrap();
+        let notify = config.action[0].action.notify.as_ref().unwrap();
         assert_eq!(notify.timeout, Timeout::Default);
     }
 
@@ -370,6 +1288,146 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_command_forms() {
+        let toml_str = r#"
+        [[action]]
+        percentage = 0.2
+        command = ["notify-send", "Battery at $percentage%"]
+        cwd = "/opt/powersave"
+        env = { PROFILE = "battery" }
+
+        [[action]]
+        percentage = 0.1
+        command = "brightnessctl get | logger"
+        shell = true
+        "#;
+
+        let config: Config = toml::from_str(toml_str).unwrap();
+        let action = &config.action[0];
+        assert_eq!(
+            action
+                .action
+                .command
+                .as_ref()
+                .unwrap()
+                .args(false, |arg| arg.replace("$percentage", "20")),
+            vec!["notify-send", "Battery at 20%"]
+        );
+        assert!(!action.action.shell);
+        assert_eq!(action.action.cwd, Some(PathBuf::from("/opt/powersave")));
+        assert_eq!(action.action.env["PROFILE"], "battery");
+        let action = &config.action[1];
+        assert_eq!(
+            action
+                .action
+                .command
+                .as_ref()
+                .unwrap()
+                .args(action.action.shell, str::to_string),
+            vec!["/bin/sh", "-c", "brightnessctl get | logger"]
+        );
+        assert!(action.action.env.is_empty());
+
+        // Placeholders are only filled in outside of shell code
+        let command = Command::Args(vec![
+            String::from("logger \"$1\""),
+            String::from("$percentage"),
+        ]);
+        assert_eq!(
+            command.args(true, |arg| arg.replace("$percentage", "20")),
+            vec!["/bin/sh", "-c", "logger \"$1\"", "sh", "20"]
+        );
+    }
+
+    #[test]
+    fn test_multiple_commands() {
+        let toml_str = r#"
+        [[action]]
+        percentage = 0.2
+        commands = ["powerprofilesctl set power-saver", ["brightnessctl", "set", "30%"]]
+        mode = "parallel"
+        stop_on_failure = true
+
+        [[action]]
+        percentage = 0.1
+        command = "systemctl hibernate"
+        "#;
+
+        let config: Config = toml::from_str(toml_str).unwrap();
+        let action = &config.action[0];
+        assert_eq!(action.action.command, None);
+        assert_eq!(
+            action.action.commands,
+            vec![
+                Command::Line(String::from("powerprofilesctl set power-saver")),
+                Command::Args(vec![
+                    String::from("brightnessctl"),
+                    String::from("set"),
+                    String::from("30%")
+                ])
+            ]
+        );
+        assert_eq!(action.action.mode, CommandMode::Parallel);
+        assert!(action.action.stop_on_failure);
+        let action = &config.action[1];
+        assert!(action.action.commands.is_empty());
+        assert_eq!(action.action.mode, CommandMode::Sequential);
+        assert!(!action.action.stop_on_failure);
+
+        let result: Result<Config, _> =
+            toml::from_str("[[action]]\npercentage = 0.2\ncommands = [\"true\", \"\"]");
+        assert!(result
+            .unwrap_err()
+            .message()
+            .contains("Command must not be empty"));
+    }
+
+    #[test]
+    fn test_system_actions() {
+        let toml_str = r#"
+        [[action]]
+        percentage = 0.05
+        system = "hybrid-sleep"
+        system_fallback = "poweroff"
+
+        [[temperature]]
+        above = 60
+        system = "suspend"
+        "#;
+
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(
+            config.action[0].action.system,
+            Some(SystemAction::HybridSleep)
+        );
+        assert_eq!(
+            config.action[0].action.system_fallback,
+            Some(SystemAction::Poweroff)
+        );
+        assert_eq!(
+            config.temperature[0].action.system,
+            Some(SystemAction::Suspend)
+        );
+        assert_eq!(config.temperature[0].action.system_fallback, None);
+
+        let result: Result<Config, _> =
+            toml::from_str("[[action]]\npercentage = 0.05\nsystem = \"reboot\"");
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("unknown variant `reboot`"));
+    }
+
+    #[test]
+    fn test_invalid_command() {
+        for command in [r#""""#, "[]", "42", "[1, 2]"] {
+            let toml_str = format!("[[action]]\npercentage = 0.2\ncommand = {}", command);
+            let result: Result<Config, _> = toml::from_str(&toml_str);
+            assert!(result.is_err(), "{}", command);
+        }
+    }
+
     #[test]
     fn test_xdg_config_home() {
         let _lock = ENV_VAR_MUTEX.lock().unwrap();
@@ -396,6 +1454,23 @@ mod tests {
         assert_eq!(config_home, PathBuf::from("/.config"));
     }
 
+    #[test]
+    fn test_xdg_state_home() {
+        let _lock = ENV_VAR_MUTEX.lock().unwrap();
+        env::set_var("XDG_STATE_HOME", "/home/battered/.local/state");
+        let state_home = xdg_state_home();
+        assert_eq!(state_home, PathBuf::from("/home/battered/.local/state"));
+    }
+
+    #[test]
+    fn test_xdg_state_home_from_home_var() {
+        let _lock = ENV_VAR_MUTEX.lock().unwrap();
+        env::remove_var("XDG_STATE_HOME");
+        env::set_var("HOME", "/home/battered");
+        let state_home = xdg_state_home();
+        assert_eq!(state_home, PathBuf::from("/home/battered/.local/state"));
+    }
+
     #[test]
     fn test_valid_onac_action() {
         let toml_str = r#"
@@ -416,23 +1491,889 @@ mod tests {
         "#;
 
         let config: Config = toml::from_str(toml_str).unwrap();
-        let on_ac = config.on_ac.clone();
+        let on_ac = config.on_ac;
         assert_eq!(config.interval, Duration::from_secs(120));
-        assert_eq!(config.action[0].percentage, 0.84);
+        assert_eq!(config.action[0].percentage, Some(0.84));
         assert_eq!(
-            on_ac.as_ref().unwrap().command,
-            Some(vec![
-                "./powersave.sh".to_string(),
-                "profile".to_string(),
-                "ac".to_string()
-            ])
+            on_ac.as_ref().unwrap().action.command,
+            Some(Command::Line("./powersave.sh profile ac".to_string()))
         );
-        let notify = on_ac.as_ref().unwrap().notify.as_ref().unwrap();
+        let notify = on_ac.as_ref().unwrap().action.notify.as_ref().unwrap();
         assert_eq!(notify.summary, "Battery charging");
         assert_eq!(notify.urgency, Urgency::Low);
         assert_eq!(notify.icon, "battery-good-charging");
     }
 
+    #[test]
+    fn test_valid_power_draw_action() {
+        let toml_str = r#"
+        [[action]]
+        percentage = 0.5
+
+        [[power_draw]]
+        watts = 35.0
+        duration = 120
+        command = "./powersave.sh enable"
+        [power_draw.notify]
+        summary = "High power draw"
+        body = "Drawing $energy_rate W"
+
+        [[power_draw]]
+        watts = 20
+        "#;
+
+        let config: Config = toml::from_str(toml_str).unwrap();
+        let power_draw = &config.power_draw[0];
+        assert_eq!(power_draw.watts, 35.0);
+        assert_eq!(power_draw.duration, Duration::from_secs(120));
+        assert_eq!(
+            power_draw.action.command,
+            Some(Command::Line("./powersave.sh enable".to_string()))
+        );
+        assert_eq!(
+            power_draw.action.notify.as_ref().unwrap().summary,
+            "High power draw"
+        );
+        assert_eq!(config.power_draw[1].watts, 20.0);
+        assert_eq!(config.power_draw[1].duration, Duration::from_secs(300));
+    }
+
+    #[test]
+    fn test_invalid_power_draw_watts() {
+        let toml_str = r#"
+        [[action]]
+        percentage = 0.5
+
+        [[power_draw]]
+        watts = -5.0
+        "#;
+
+        let result: Result<Config, toml::de::Error> = toml::from_str(toml_str);
+        assert!(result.is_err());
+        assert_eq!(
+            result.unwrap_err().message(),
+            "value must be greater than 0"
+        );
+    }
+
+    #[test]
+    fn test_valid_drop_action() {
+        let toml_str = r#"
+        [[action]]
+        percentage = 0.5
+
+        [[drop]]
+        by = 0.2
+        within = 300
+        [drop.notify]
+        summary = "Battery dropped by $drop%"
+        body = "Losing $drop_rate% per minute"
+
+        [[drop]]
+        by = 0.1
+        "#;
+
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.drop[0].by, 0.2);
+        assert_eq!(config.drop[0].within, Duration::from_secs(300));
+        assert_eq!(
+            config.drop[0].action.notify.as_ref().unwrap().summary,
+            "Battery dropped by $drop%"
+        );
+        assert_eq!(config.drop[1].within, Duration::from_secs(600));
+    }
+
+    #[test]
+    fn test_invalid_drop_action() {
+        let toml_str = r#"
+        [[action]]
+        percentage = 0.5
+
+        [[drop]]
+        by = 20
+        "#;
+
+        let result: Result<Config, toml::de::Error> = toml::from_str(toml_str);
+        assert!(result.is_err());
+        assert_eq!(
+            result.unwrap_err().message(),
+            "value must be between 0 and 1"
+        );
+    }
+
+    #[test]
+    fn test_valid_temperature_action() {
+        let toml_str = r#"
+        [[action]]
+        percentage = 0.5
+
+        [[temperature]]
+        above = 50.0
+        hysteresis = 5.0
+        command = "powerprofilesctl set power-saver"
+        [temperature.notify]
+        summary = "Battery at $temperature °C"
+        urgency = "Critical"
+
+        [[temperature]]
+        above = 45
+        name = "hot"
+        tags = ["heat"]
+        enabled = false
+        days = ["sat"]
+        when = "state == 'charging'"
+        "#;
+
+        let config: Config = toml::from_str(toml_str).unwrap();
+        let temperature = &config.temperature[0];
+        assert_eq!(temperature.above, 50.0);
+        assert_eq!(temperature.hysteresis, 5.0);
+        assert_eq!(
+            temperature.action.command,
+            Some(Command::Line(
+                "powerprofilesctl set power-saver".to_string()
+            ))
+        );
+        let notify = temperature.action.notify.as_ref().unwrap();
+        assert_eq!(notify.urgency, Urgency::Critical);
+        assert_eq!(config.temperature[1].above, 45.0);
+        assert_eq!(config.temperature[1].hysteresis, 2.0);
+        let action = &config.temperature[1].action;
+        assert_eq!(action.name.as_deref(), Some("hot"));
+        assert_eq!(action.tags, vec!["heat"]);
+        assert!(!action.is_enabled());
+        assert_eq!(action.days, vec![Weekday::Sat]);
+        assert!(action.when.is_some());
+    }
+
+    #[test]
+    fn test_invalid_temperature_hysteresis() {
+        let toml_str = r#"
+        [[action]]
+        percentage = 0.5
+
+        [[temperature]]
+        above = 50.0
+        hysteresis = -1.0
+        "#;
+
+        let result: Result<Config, toml::de::Error> = toml::from_str(toml_str);
+        assert!(result.is_err());
+        assert_eq!(result.unwrap_err().message(), "value must not be negative");
+    }
+
+    #[test]
+    fn test_valid_health_action() {
+        let toml_str = r#"
+        [[action]]
+        percentage = 0.5
+
+        [[health]]
+        below = 0.8
+        cycle_count = 1000
+        [health.notify]
+        summary = "Battery worn out"
+        body = "Health at $health%"
+
+        [[health]]
+        cycle_count = 500
+        "#;
+
+        let config: Config = toml::from_str(toml_str).unwrap();
+        let health = &config.health[0];
+        assert_eq!(health.below, Some(0.8));
+        assert_eq!(health.cycle_count, Some(1000));
+        assert_eq!(health.action.command, None);
+        assert_eq!(
+            health.action.notify.as_ref().unwrap().summary,
+            "Battery worn out"
+        );
+        assert_eq!(config.health[1].below, None);
+        assert_eq!(config.health[1].cycle_count, Some(500));
+    }
+
+    #[test]
+    fn test_invalid_health_values() {
+        let test_values = [
+            r#"
+            [[action]]
+            percentage = 0.5
+            [[health]]
+            below = 80
+            "#,
+            r#"
+            [[action]]
+            percentage = 0.5
+            [[health]]
+            cycle_count = -1
+            "#,
+        ];
+
+        for value in test_values {
+            let result: Result<Config, toml::de::Error> = toml::from_str(value);
+            assert!(result.is_err());
+        }
+    }
+
+    #[test]
+    fn test_health_without_limit() {
+        let toml_str = r#"
+        [[health]]
+        below = 0.8
+
+        [[health]]
+        command = "true"
+        "#;
+
+        let result: Result<Config, toml::de::Error> = toml::from_str(toml_str);
+        assert_eq!(
+            result.unwrap_err().message(),
+            "missing field `below` or `cycle_count` in [[health]] #2"
+        );
+    }
+
+    #[test]
+    fn test_valid_hysteresis() {
+        let toml_str = r#"
+        hysteresis = 0.02
+
+        [[action]]
+        percentage = 0.2
+        hysteresis = 0.05
+
+        [[action]]
+        percentage = 0.5
+        "#;
+
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.hysteresis, Some(0.02));
+        assert_eq!(config.action[0].hysteresis, Some(0.05));
+        assert_eq!(config.action[1].hysteresis, None);
+    }
+
+    #[test]
+    fn test_valid_energy_thresholds() {
+        let toml_str = r#"
+        [[action]]
+        energy_below = "8 Wh"
+
+        [[action]]
+        energy_below = "12.5Wh"
+
+        [[action]]
+        percentage = 0.2
+        capacity = "design"
+        "#;
+
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.action[0].energy_below, Some(8.0));
+        assert_eq!(config.action[1].energy_below, Some(12.5));
+        assert_eq!(config.action[1].capacity, Capacity::Full);
+        assert_eq!(config.action[2].capacity, Capacity::Design);
+    }
+
+    #[test]
+    fn test_resolve_threshold() {
+        let mut trigger = Trigger {
+            energy_below: Some(8.0),
+            ..Default::default()
+        };
+        trigger.resolve_threshold(40.0, 50.0);
+        assert_eq!(trigger.threshold(), Some(0.2));
+
+        let mut trigger = Trigger {
+            percentage: Some(0.2),
+            capacity: Capacity::Design,
+            ..Default::default()
+        };
+        trigger.resolve_threshold(40.0, 50.0);
+        assert_eq!(trigger.threshold(), Some(0.25));
+
+        let mut trigger = Trigger {
+            percentage: Some(0.2),
+            ..Default::default()
+        };
+        trigger.resolve_threshold(40.0, 50.0);
+        assert_eq!(trigger.threshold(), Some(0.2));
+
+        // Unknown full energy disables energy thresholds
+        let mut trigger = Trigger {
+            energy_below: Some(8.0),
+            ..Default::default()
+        };
+        trigger.resolve_threshold(0.0, 50.0);
+        assert_eq!(trigger.threshold(), None);
+    }
+
+    #[test]
+    fn test_valid_name_and_tags() {
+        let toml_str = r#"
+        [[action]]
+        name = "powersave-low"
+        tags = ["powersave", "low"]
+        enabled = false
+        percentage = 0.2
+        "#;
+
+        let config: Config = toml::from_str(toml_str).unwrap();
+        let action = &config.action[0];
+        assert!(!action.action.is_enabled());
+        assert_eq!(
+            action.action.selectors().collect::<Vec<_>>(),
+            vec!["powersave-low", "powersave", "low"]
+        );
+    }
+
+    #[test]
+    fn test_defaults() {
+        let toml_str = r#"
+        [defaults.notify]
+        urgency = "Critical"
+        icon = "battery-caution"
+        timeout = 0
+
+        [defaults.action]
+        hysteresis = 0.02
+        tags = ["low"]
+        timeout = 30
+
+        [[action]]
+        percentage = 0.2
+        [action.notify]
+        summary = "Battery low"
+
+        [[action]]
+        percentage = 0.5
+        hysteresis = 0.05
+        [action.notify]
+        summary = "Battery half empty"
+        urgency = "Low"
+
+        [[temperature]]
+        above = 50.0
+        [temperature.notify]
+        summary = "Battery hot"
+        "#;
+
+        let config = parse_config(toml_str).unwrap();
+        let notify = config.action[0].action.notify.as_ref().unwrap();
+        assert_eq!(notify.urgency, Urgency::Critical);
+        assert_eq!(notify.icon, "battery-caution");
+        assert_eq!(notify.timeout, Timeout::Never);
+        assert_eq!(config.action[0].hysteresis, Some(0.02));
+        assert_eq!(config.action[0].action.tags, vec!["low"]);
+
+        // Per-action overrides
+        let notify = config.action[1].action.notify.as_ref().unwrap();
+        assert_eq!(notify.urgency, Urgency::Low);
+        assert_eq!(notify.icon, "battery-caution");
+        assert_eq!(config.action[1].hysteresis, Some(0.05));
+
+        // Other sections only inherit settings of `Action`
+        let notify = config.temperature[0].action.notify.as_ref().unwrap();
+        assert_eq!(notify.urgency, Urgency::Critical);
+        assert_eq!(
+            config.temperature[0].action.timeout,
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(config.temperature[0].hysteresis, 2.0);
+    }
+
+    #[test]
+    fn test_defaults_with_notify_for_actions() {
+        let toml_str = r#"
+        [defaults.action.notify]
+        summary = "Battery at $percentage%"
+
+        [[action]]
+        percentage = 0.2
+
+        [[power_draw]]
+        watts = 30.0
+        "#;
+
+        let config = parse_config(toml_str).unwrap();
+        let notify = config.action[0].action.notify.as_ref().unwrap();
+        assert_eq!(notify.summary, "Battery at $percentage%");
+        let notify = config.power_draw[0].action.notify.as_ref().unwrap();
+        assert_eq!(notify.summary, "Battery at $percentage%");
+    }
+
+    #[test]
+    fn test_invalid_defaults() {
+        let test_values = [
+            (
+                r#"
+                [defaults.notify]
+                icon = "battery-caution"
+
+                [[action]]
+                percentage = 0.2
+                [action.notify]
+                summary = "Battery low"
+
+                [[action]]
+                percentage = 0.5
+                [action.notify]
+                body = "No summary"
+                "#,
+                "missing field `summary` in notify of [[action]] #2, and not set in [defaults.notify]",
+            ),
+            (
+                r#"
+                [defaults.notify]
+                icon = "battery-caution"
+
+                [on_ac.notify]
+                urgency = "Low"
+                "#,
+                "missing field `summary` in notify of [on_ac], and not set in [defaults.notify]",
+            ),
+            (
+                r#"
+                [defaults.actions]
+                hysteresis = 0.02
+                "#,
+                "Unknown section [defaults.actions]",
+            ),
+            (
+                r#"
+                defaults = 5
+                "#,
+                "[defaults] must be a table",
+            ),
+            (
+                r#"
+                [defaults.action]
+                on = "above"
+
+                [[action]]
+                percentage = 0.2
+                "#,
+                "`on` can't be set in [defaults.action]",
+            ),
+            (
+                r#"
+                [defaults.notify]
+                urgency = "Loud"
+
+                [[action]]
+                percentage = 0.2
+                [action.notify]
+                summary = "Battery low"
+                "#,
+                "Failed to parse notification urgency",
+            ),
+        ];
+
+        for (value, message) in test_values {
+            let result = parse_config(value);
+            assert!(
+                result.as_ref().unwrap_err().to_string().contains(message),
+                "{:?}",
+                result.unwrap_err().to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn test_legacy_sections_with_conflicting_event() {
+        let test_values = [
+            (
+                r#"
+                [on_ac]
+                on = "below"
+                percentage = 0.5
+                "#,
+                "`on = \"below\"` conflicts with [on_ac], which always triggers on 'ac_connected'; use [[trigger]] instead",
+            ),
+            (
+                r#"
+                [[action]]
+                percentage = 0.2
+
+                [[action]]
+                on = "above"
+                percentage = 0.8
+                "#,
+                "`on = \"above\"` conflicts with [[action]] #2, which always triggers on 'below'; use [[trigger]] instead",
+            ),
+        ];
+
+        for (value, message) in test_values {
+            let result = parse_config(value);
+            assert_eq!(result.unwrap_err().to_string(), message);
+        }
+
+        // Repeating the event of the section is fine
+        let config = parse_config(
+            r#"
+            [on_ac]
+            on = "ac_connected"
+
+            [[action]]
+            on = "below"
+            percentage = 0.2
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.on_ac.unwrap().on, Event::AcConnected);
+        assert_eq!(config.action[0].on, Event::Below);
+    }
+
+    #[test]
+    fn test_valid_on_failure() {
+        let toml_str = r#"
+        on_failure = "disable_action"
+
+        [[action]]
+        percentage = 0.2
+        on_failure = "retry"
+        retries = 3
+        retry_delay = 10
+        notify_output = true
+
+        [[action]]
+        percentage = 0.1
+
+        [[power_draw]]
+        watts = 30.0
+        on_failure = "exit"
+        "#;
+
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.on_failure, OnFailure::DisableAction);
+        assert_eq!(config.action[0].action.on_failure, Some(OnFailure::Retry));
+        assert_eq!(config.action[0].action.retries, Some(3));
+        assert_eq!(
+            config.action[0].action.retry_delay,
+            Some(Duration::from_secs(10))
+        );
+        assert!(config.action[0].action.notify_output);
+        assert_eq!(config.action[1].action.on_failure, None);
+        assert_eq!(
+            config.power_draw[0].action.on_failure,
+            Some(OnFailure::Exit)
+        );
+    }
+
+    #[test]
+    fn test_invalid_on_failure() {
+        let toml_str = r#"
+        on_failure = "ignore"
+
+        [[action]]
+        percentage = 0.2
+        "#;
+
+        let result: Result<Config, _> = toml::from_str(toml_str);
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("unknown variant `ignore`"));
+    }
+
+    #[test]
+    fn test_valid_exclusive() {
+        let toml_str = r#"
+        exclusive = false
+
+        [[action]]
+        percentage = 0.2
+        exclusive = true
+
+        [[action]]
+        percentage = 0.5
+        "#;
+
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert!(!config.exclusive);
+        assert_eq!(config.action[0].exclusive, Some(true));
+        assert_eq!(config.action[1].exclusive, None);
+    }
+
+    #[test]
+    fn test_invalid_hysteresis() {
+        let toml_str = r#"
+        [[action]]
+        percentage = 0.2
+        hysteresis = 2
+        "#;
+
+        let result: Result<Config, toml::de::Error> = toml::from_str(toml_str);
+        assert!(result.is_err());
+        assert_eq!(
+            result.unwrap_err().message(),
+            "value must be between 0 and 1"
+        );
+    }
+
+    #[test]
+    fn test_valid_repeat() {
+        let toml_str = r#"
+        [[action]]
+        percentage = 0.1
+        repeat = 120
+        repeat_limit = 5
+        repeat_command = true
+        [action.notify]
+        summary = "Battery critical!"
+        "#;
+
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.action[0].repeat, Some(Duration::from_secs(120)));
+        assert_eq!(config.action[0].repeat_limit, Some(5));
+        assert!(config.action[0].repeat_command);
+    }
+
+    #[test]
+    fn test_invalid_repeat() {
+        let test_values = [
+            r#"
+            [[action]]
+            percentage = 0.1
+            repeat = -60
+            "#,
+            r#"
+            [[action]]
+            percentage = 0.1
+            repeat_limit = -1
+            "#,
+        ];
+
+        for value in test_values {
+            let result: Result<Config, toml::de::Error> = toml::from_str(value);
+            assert!(result.is_err());
+        }
+    }
+
+    #[test]
+    fn test_valid_countdown() {
+        let toml_str = r#"
+        [[action]]
+        percentage = 0.05
+        command = "systemctl hibernate"
+        countdown = 60
+        countdown_interval = 10
+        [action.notify]
+        summary = "Hibernating in $countdown seconds unless you plug in"
+        "#;
+
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.action[0].countdown, Some(Duration::from_secs(60)));
+        assert_eq!(config.action[0].countdown_interval, Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_valid_schedule() {
+        let toml_str = r#"
+        [[action]]
+        percentage = 0.2
+        active_hours = "08:00-22:00"
+        days = ["mon-fri", "Sunday"]
+
+        [on_ac]
+        active_hours = "22:00 - 06:30"
+        days = ["sat"]
+        "#;
+
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(
+            config.action[0].action.active_hours,
+            Some(ActiveHours {
+                start: NaiveTime::from_hms_opt(8, 0, 0).unwrap(),
+                end: NaiveTime::from_hms_opt(22, 0, 0).unwrap(),
+            })
+        );
+        assert_eq!(
+            config.action[0].action.days,
+            vec![
+                Weekday::Mon,
+                Weekday::Tue,
+                Weekday::Wed,
+                Weekday::Thu,
+                Weekday::Fri,
+                Weekday::Sun
+            ]
+        );
+        let on_ac = config.on_ac.unwrap();
+        assert_eq!(
+            on_ac.action.active_hours,
+            Some(ActiveHours {
+                start: NaiveTime::from_hms_opt(22, 0, 0).unwrap(),
+                end: NaiveTime::from_hms_opt(6, 30, 0).unwrap(),
+            })
+        );
+        assert_eq!(on_ac.action.days, vec![Weekday::Sat]);
+    }
+
+    #[test]
+    fn test_weekday_range_wraps_around() {
+        let toml_str = r#"
+        [[action]]
+        percentage = 0.2
+        days = ["fri-mon"]
+        "#;
+
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(
+            config.action[0].action.days,
+            vec![Weekday::Fri, Weekday::Sat, Weekday::Sun, Weekday::Mon]
+        );
+    }
+
+    #[test]
+    fn test_invalid_schedule() {
+        let test_values = [
+            (
+                r#"
+                [[action]]
+                percentage = 0.2
+                active_hours = "08:00"
+                "#,
+                "Failed to parse active hours '08:00': expected format 'HH:MM-HH:MM'",
+            ),
+            (
+                r#"
+                [[action]]
+                percentage = 0.2
+                active_hours = "8am-10pm"
+                "#,
+                "Failed to parse active hours '8am-10pm': expected format 'HH:MM-HH:MM'",
+            ),
+            (
+                r#"
+                [[action]]
+                percentage = 0.2
+                days = ["mon", "someday"]
+                "#,
+                "Failed to parse day 'someday'",
+            ),
+        ];
+
+        for (value, message) in test_values {
+            let result: Result<Config, toml::de::Error> = toml::from_str(value);
+            assert!(result.is_err());
+            assert_eq!(result.unwrap_err().message(), message);
+        }
+    }
+
+    #[test]
+    fn test_is_scheduled() {
+        let hours = Some(ActiveHours {
+            start: NaiveTime::from_hms_opt(8, 0, 0).unwrap(),
+            end: NaiveTime::from_hms_opt(22, 0, 0).unwrap(),
+        });
+        let night = Some(ActiveHours {
+            start: NaiveTime::from_hms_opt(22, 0, 0).unwrap(),
+            end: NaiveTime::from_hms_opt(8, 0, 0).unwrap(),
+        });
+        let weekdays = [
+            Weekday::Mon,
+            Weekday::Tue,
+            Weekday::Wed,
+            Weekday::Thu,
+            Weekday::Fri,
+        ];
+        // 2025-06-25 is a Wednesday, 2025-06-28 a Saturday
+        let wednesday_noon =
+            NaiveDateTime::parse_from_str("2025-06-25 12:00", "%Y-%m-%d %H:%M").unwrap();
+        let wednesday_late =
+            NaiveDateTime::parse_from_str("2025-06-25 23:15", "%Y-%m-%d %H:%M").unwrap();
+        let saturday_noon =
+            NaiveDateTime::parse_from_str("2025-06-28 12:00", "%Y-%m-%d %H:%M").unwrap();
+
+        assert!(is_scheduled(&None, &[], &wednesday_noon));
+        assert!(is_scheduled(&hours, &weekdays, &wednesday_noon));
+        assert!(!is_scheduled(&hours, &weekdays, &wednesday_late));
+        assert!(!is_scheduled(&hours, &weekdays, &saturday_noon));
+        assert!(is_scheduled(&hours, &[], &saturday_noon));
+        assert!(is_scheduled(&night, &[], &wednesday_late));
+        assert!(!is_scheduled(&night, &[], &wednesday_noon));
+    }
+
+    #[test]
+    fn test_valid_when_expression() {
+        let toml_str = r#"
+        [[action]]
+        percentage = 0.3
+        when = "percentage < 30 && energy_rate > 20 && state == 'discharging'"
+
+        [on_ac]
+        when = "temperature < 45"
+        "#;
+
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(
+            config.action[0].action.when.as_ref().unwrap().to_string(),
+            "percentage < 30 && energy_rate > 20 && state == 'discharging'"
+        );
+        assert!(config.on_ac.unwrap().action.when.is_some());
+    }
+
+    #[test]
+    fn test_invalid_when_expression() {
+        let test_values = [
+            (
+                r#"
+                [[action]]
+                percentage = 0.5
+                when = "percentage < "
+                "#,
+                "Failed to parse expression 'percentage < ': Unexpected end of expression",
+            ),
+            (
+                r#"
+                [[action]]
+                percentage = 0.5
+                when = "state < 30"
+                "#,
+                "Failed to parse expression 'state < 30': '<' expects number, found text",
+            ),
+            (
+                r#"
+                [[action]]
+                percentage = 0.5
+                when = "percentage"
+                "#,
+                "Failed to parse expression 'percentage': Expression must evaluate to bool, found number",
+            ),
+        ];
+
+        for (value, message) in test_values {
+            let result: Result<Config, toml::de::Error> = toml::from_str(value);
+            assert!(result.is_err());
+            assert_eq!(result.unwrap_err().message(), message);
+        }
+    }
+
+    #[test]
+    fn test_ac_source() {
+        let toml_str = r#"
+        ac_source = "adapter"
+
+        [[action]]
+        percentage = 0.5
+        "#;
+
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.ac_source, AcSource::Adapter);
+
+        let toml_str = r#"
+        ac_source = "solar"
+
+        [[action]]
+        percentage = 0.5
+        "#;
+
+        let result: Result<Config, toml::de::Error> = toml::from_str(toml_str);
+        assert!(result.is_err());
+        assert!(result
+            .unwrap_err()
+            .message()
+            .starts_with("unknown variant `solar`"));
+    }
+
     #[test]
     fn test_serial_number_trimming() {
         let toml_str = r#"
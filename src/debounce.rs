@@ -0,0 +1,208 @@
+use std::time::{Duration, Instant};
+
+// Only lets a changed value through once it held for long enough, to ignore brief glitches
+#[derive(Debug)]
+pub struct Debouncer<T> {
+    duration: Option<Duration>,
+    readings: Option<u32>,
+    // Readings closer together than this, e.g. while polling commands, only count once
+    reading_interval: Duration,
+    stable: Option<T>,
+    // Changed value, when it was first and last counted, and how many readings in a row returned it
+    pending: Option<(T, Instant, Instant, u32)>,
+}
+
+impl<T: Copy + PartialEq> Debouncer<T> {
+    pub fn new(
+        duration: Option<Duration>,
+        readings: Option<u32>,
+        reading_interval: Duration,
+    ) -> Debouncer<T> {
+        Debouncer {
+            duration,
+            readings,
+            reading_interval,
+            stable: None,
+            pending: None,
+        }
+    }
+
+    pub fn update(&mut self, value: T, now: Instant) -> T {
+        let Some(stable) = self.stable else {
+            self.stable = Some(value); // First reading counts right away
+            return value;
+        };
+        if value == stable {
+            self.pending = None; // Glitch is over
+            return stable;
+        }
+        let (since, counted, count) = match self.pending {
+            Some((pending, since, counted, count)) if pending == value => {
+                if now.duration_since(counted) >= self.reading_interval {
+                    (since, now, count + 1)
+                } else {
+                    (since, counted, count)
+                }
+            }
+            _ => (now, now, 1),
+        };
+        let held = self
+            .duration
+            .is_none_or(|duration| now.duration_since(since) >= duration)
+            && self.readings.is_none_or(|readings| count >= readings);
+        if held {
+            self.stable = Some(value);
+            self.pending = None;
+            return value;
+        }
+        self.pending = Some((value, since, counted, count));
+        stable
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ac::{state_on_ac, AcTracker, AcTransition};
+    use starship_battery::State;
+
+    // Battery reporting a given state at given seconds since start
+    struct FakeBattery {
+        readings: Vec<(u64, State)>,
+    }
+
+    impl FakeBattery {
+        // Debounced transitions of the power supply over all readings
+        fn transitions(&self, debouncer: &mut Debouncer<State>) -> Vec<AcTransition> {
+            let start = Instant::now();
+            let mut tracker = AcTracker::default();
+            self.readings
+                .iter()
+                .filter_map(|(seconds, state)| {
+                    let state = debouncer.update(*state, start + Duration::from_secs(*seconds));
+                    tracker.update(state_on_ac(&state))
+                })
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_brief_glitches_are_ignored() {
+        let battery = FakeBattery {
+            readings: vec![
+                (0, State::Discharging),
+                (1, State::Charging), // Loose cable
+                (2, State::Discharging),
+                (3, State::Discharging),
+                (4, State::Charging),
+                (5, State::Discharging),
+            ],
+        };
+        let mut debouncer = Debouncer::new(Some(Duration::from_secs(3)), None, Duration::ZERO);
+        assert_eq!(battery.transitions(&mut debouncer), vec![]);
+        assert!(!debouncer.is_pending());
+    }
+
+    #[test]
+    fn test_change_counts_after_duration() {
+        let battery = FakeBattery {
+            readings: vec![
+                (0, State::Discharging),
+                (1, State::Charging),
+                (2, State::Charging),
+                (4, State::Charging),
+                (5, State::Discharging),
+                (8, State::Discharging),
+            ],
+        };
+        let mut debouncer = Debouncer::new(Some(Duration::from_secs(3)), None, Duration::ZERO);
+        assert_eq!(
+            battery.transitions(&mut debouncer),
+            vec![AcTransition::Connected, AcTransition::Disconnected]
+        );
+    }
+
+    #[test]
+    fn test_change_counts_after_readings() {
+        let mut debouncer = Debouncer::new(None, Some(3), Duration::ZERO);
+        let start = Instant::now();
+        assert_eq!(
+            debouncer.update(State::Discharging, start),
+            State::Discharging
+        );
+        assert_eq!(debouncer.update(State::Charging, start), State::Discharging);
+        assert_eq!(debouncer.update(State::Charging, start), State::Discharging);
+        assert!(debouncer.is_pending());
+        assert_eq!(debouncer.update(State::Charging, start), State::Charging);
+        assert!(!debouncer.is_pending());
+
+        // Switching to another changed value starts over
+        assert_eq!(debouncer.update(State::Full, start), State::Charging);
+        assert_eq!(debouncer.update(State::Discharging, start), State::Charging);
+        assert_eq!(debouncer.update(State::Full, start), State::Charging);
+        assert_eq!(debouncer.update(State::Full, start), State::Charging);
+        assert_eq!(debouncer.update(State::Full, start), State::Full);
+    }
+
+    #[test]
+    fn test_fast_polls_count_as_one_reading() {
+        // Commands or countdowns make the loop check more often than every 60 seconds
+        let battery = FakeBattery {
+            readings: vec![
+                (0, State::Discharging),
+                (60, State::Charging),
+                (61, State::Charging),
+                (62, State::Charging),
+                (119, State::Charging),
+                (120, State::Charging),
+            ],
+        };
+        let mut debouncer = Debouncer::new(None, Some(2), Duration::from_secs(60));
+        let start = Instant::now();
+        let states: Vec<_> = battery
+            .readings
+            .iter()
+            .map(|(seconds, state)| debouncer.update(*state, start + Duration::from_secs(*seconds)))
+            .collect();
+        assert_eq!(states[..5], [State::Discharging; 5]);
+        assert_eq!(states[5], State::Charging);
+    }
+
+    #[test]
+    fn test_duration_and_readings() {
+        // Both have to be met
+        let battery = FakeBattery {
+            readings: vec![
+                (0, State::Charging),
+                (10, State::Discharging),
+                (20, State::Discharging),
+                (21, State::Discharging),
+            ],
+        };
+        let mut debouncer = Debouncer::new(Some(Duration::from_secs(5)), Some(3), Duration::ZERO);
+        assert_eq!(
+            battery.transitions(&mut debouncer),
+            vec![AcTransition::Disconnected]
+        );
+
+        let mut debouncer = Debouncer::new(Some(Duration::from_secs(15)), Some(2), Duration::ZERO);
+        let start = Instant::now();
+        debouncer.update(true, start);
+        assert!(debouncer.update(false, start + Duration::from_secs(10)));
+        assert!(debouncer.update(false, start + Duration::from_secs(20)));
+        assert!(!debouncer.update(false, start + Duration::from_secs(25)));
+    }
+
+    #[test]
+    fn test_no_debounce() {
+        let mut debouncer = Debouncer::new(None, None, Duration::ZERO);
+        let start = Instant::now();
+        assert!(debouncer.update(true, start));
+        assert!(!debouncer.update(false, start));
+        assert!(debouncer.update(true, start));
+    }
+}
@@ -0,0 +1,594 @@
+use crate::template::Template;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Type {
+    Number,
+    Text,
+    Bool,
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Number => write!(f, "number"),
+            Type::Text => write!(f, "text"),
+            Type::Bool => write!(f, "bool"),
+        }
+    }
+}
+
+// Limit for nested parentheses, unary and binary operators, keeping recursion bounded
+const MAX_DEPTH: usize = 64;
+
+// Variables available in expressions; these are the same as the template placeholders
+const VARIABLES: &[(&str, Type)] = &[
+    ("percentage", Type::Number),
+    ("energy_rate", Type::Number),
+    ("energy", Type::Number),
+    ("temperature", Type::Number),
+    ("health", Type::Number),
+    ("energy_full", Type::Number),
+    ("energy_full_design", Type::Number),
+    ("cycle_count", Type::Number),
+    ("countdown", Type::Number),
+    ("drop", Type::Number),
+    ("drop_rate", Type::Number),
+    ("state", Type::Text),
+    ("time_to_empty", Type::Number),
+    ("serial", Type::Text),
+];
+
+// Expressions are checked before a countdown starts, so it is always 0 there
+const UNAVAILABLE: &[&str] = &["countdown"];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Number(f64),
+    Text(String),
+    Bool(bool),
+    // Value not reported by the battery, e.g. temperature
+    Missing,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Text(String),
+    Ident(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BinaryOp {
+    And,
+    Or,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Literal(Value),
+    Variable(String),
+    Not(Box<Node>),
+    Negate(Box<Node>),
+    Binary(BinaryOp, Box<Node>, Box<Node>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Expression {
+    source: String,
+    root: Node,
+}
+
+impl Expression {
+    pub fn parse(source: &str) -> Result<Expression, String> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            depth: 0,
+        };
+        let root = parser.parse_or()?;
+        if let Some(token) = parser.peek() {
+            return Err(format!("Unexpected token {:?}", token));
+        }
+        match check(&root)? {
+            Type::Bool => Ok(Expression {
+                source: source.to_string(),
+                root,
+            }),
+            other => Err(format!("Expression must evaluate to bool, found {}", other)),
+        }
+    }
+
+    pub fn evaluate<T: Template>(&self, format_obj: &T) -> bool {
+        let variables = variables_from_template(format_obj);
+        eval(&self.root, &variables) == Value::Bool(true)
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+fn variables_from_template<T: Template>(format_obj: &T) -> Vec<(String, Value)> {
+    let mut variables = Vec::new();
+    for line in format_obj.to_template().lines() {
+        let Some((name, value)) = line.split_once(": ") else {
+            continue;
+        };
+        let value = match variable_type(name) {
+            Some(Type::Number) => value.parse().map_or(Value::Missing, Value::Number),
+            Some(Type::Text) => Value::Text(value.to_string()),
+            _ => continue,
+        };
+        variables.push((name.to_string(), value));
+    }
+    variables
+}
+
+fn variable_type(name: &str) -> Option<Type> {
+    VARIABLES
+        .iter()
+        .find(|(variable, _)| *variable == name)
+        .map(|(_, variable_type)| *variable_type)
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_digit() || c == '.' {
+            let mut end = start;
+            while let Some(&(i, c)) = chars.peek() {
+                if !(c.is_ascii_digit() || c == '.') {
+                    break;
+                }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            let number = &source[start..end];
+            let value = number
+                .parse()
+                .map_err(|_| format!("Invalid number '{}'", number))?;
+            tokens.push(Token::Number(value));
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let mut end = start;
+            while let Some(&(i, c)) = chars.peek() {
+                if !(c.is_ascii_alphanumeric() || c == '_') {
+                    break;
+                }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            tokens.push(Token::Ident(source[start..end].to_string()));
+        } else if c == '"' || c == '\'' {
+            chars.next();
+            let mut text = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, quote)) if quote == c => break,
+                    Some((_, other)) => text.push(other),
+                    None => return Err(String::from("Missing closing quote")),
+                }
+            }
+            tokens.push(Token::Text(text));
+        } else if c == '(' {
+            chars.next();
+            tokens.push(Token::LParen);
+        } else if c == ')' {
+            chars.next();
+            tokens.push(Token::RParen);
+        } else {
+            const OPERATORS: &[&str] = &[
+                "&&", "||", "==", "!=", "<=", ">=", "<", ">", "!", "+", "-", "*", "/",
+            ];
+            let rest = &source[start..];
+            let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(*op)) else {
+                return Err(format!("Unexpected character '{}'", c));
+            };
+            for _ in 0..op.len() {
+                chars.next();
+            }
+            tokens.push(Token::Op(op));
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser {
+    // Go one level deeper into the expression, the caller restores `depth` afterwards
+    fn descend(&mut self) -> Result<(), String> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(format!(
+                "Expression nested deeper than {} levels",
+                MAX_DEPTH
+            ));
+        }
+        Ok(())
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    // Consume the next token if it is one of the given operators
+    fn next_op(&mut self, ops: &[(&str, BinaryOp)]) -> Option<BinaryOp> {
+        let Some(Token::Op(op)) = self.peek() else {
+            return None;
+        };
+        let found = ops.iter().find(|(candidate, _)| candidate == op)?.1;
+        self.pos += 1;
+        Some(found)
+    }
+
+    fn parse_binary(
+        &mut self,
+        ops: &[(&str, BinaryOp)],
+        operand: fn(&mut Parser) -> Result<Node, String>,
+    ) -> Result<Node, String> {
+        let depth = self.depth;
+        let mut left = operand(self)?;
+        while let Some(op) = self.next_op(ops) {
+            self.descend()?; // Every operator nests the left side one level deeper
+            let right = operand(self)?;
+            left = Node::Binary(op, Box::new(left), Box::new(right));
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    fn parse_or(&mut self) -> Result<Node, String> {
+        self.parse_binary(&[("||", BinaryOp::Or)], Parser::parse_and)
+    }
+
+    fn parse_and(&mut self) -> Result<Node, String> {
+        self.parse_binary(&[("&&", BinaryOp::And)], Parser::parse_comparison)
+    }
+
+    fn parse_comparison(&mut self) -> Result<Node, String> {
+        let left = self.parse_additive()?;
+        let ops = [
+            ("==", BinaryOp::Eq),
+            ("!=", BinaryOp::Ne),
+            ("<=", BinaryOp::Le),
+            (">=", BinaryOp::Ge),
+            ("<", BinaryOp::Lt),
+            (">", BinaryOp::Gt),
+        ];
+        match self.next_op(&ops) {
+            Some(op) => {
+                let right = self.parse_additive()?;
+                Ok(Node::Binary(op, Box::new(left), Box::new(right)))
+            }
+            None => Ok(left),
+        }
+    }
+
+    fn parse_additive(&mut self) -> Result<Node, String> {
+        self.parse_binary(
+            &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+            Parser::parse_multiplicative,
+        )
+    }
+
+    fn parse_multiplicative(&mut self) -> Result<Node, String> {
+        self.parse_binary(
+            &[("*", BinaryOp::Mul), ("/", BinaryOp::Div)],
+            Parser::parse_unary,
+        )
+    }
+
+    fn parse_unary(&mut self) -> Result<Node, String> {
+        let negate: fn(Box<Node>) -> Node = match self.peek() {
+            Some(Token::Op("!")) => Node::Not,
+            Some(Token::Op("-")) => Node::Negate,
+            _ => return self.parse_primary(),
+        };
+        self.pos += 1;
+        self.descend()?;
+        let node = negate(Box::new(self.parse_unary()?));
+        self.depth -= 1;
+        Ok(node)
+    }
+
+    fn parse_primary(&mut self) -> Result<Node, String> {
+        match self.next() {
+            Some(Token::Number(value)) => Ok(Node::Literal(Value::Number(value))),
+            Some(Token::Text(value)) => Ok(Node::Literal(Value::Text(value))),
+            Some(Token::Ident(name)) => match name.as_str() {
+                "true" => Ok(Node::Literal(Value::Bool(true))),
+                "false" => Ok(Node::Literal(Value::Bool(false))),
+                _ => Ok(Node::Variable(name)),
+            },
+            Some(Token::LParen) => {
+                self.descend()?;
+                let node = self.parse_or()?;
+                self.depth -= 1;
+                match self.next() {
+                    Some(Token::RParen) => Ok(node),
+                    _ => Err(String::from("Missing closing parenthesis")),
+                }
+            }
+            Some(token) => Err(format!("Unexpected token {:?}", token)),
+            None => Err(String::from("Unexpected end of expression")),
+        }
+    }
+}
+
+// Determine the type of an expression, rejecting invalid combinations
+fn check(node: &Node) -> Result<Type, String> {
+    let expect = |node: &Node, expected: Type, context: &str| {
+        let found = check(node)?;
+        if found != expected {
+            return Err(format!(
+                "'{}' expects {}, found {}",
+                context, expected, found
+            ));
+        }
+        Ok(())
+    };
+    match node {
+        Node::Literal(Value::Number(_)) => Ok(Type::Number),
+        Node::Literal(Value::Text(_)) => Ok(Type::Text),
+        Node::Literal(_) => Ok(Type::Bool),
+        Node::Variable(name) if UNAVAILABLE.contains(&name.as_str()) => Err(format!(
+            "Variable '{}' is not available in expressions",
+            name
+        )),
+        Node::Variable(name) => {
+            variable_type(name).ok_or_else(|| format!("Unknown variable '{}'", name))
+        }
+        Node::Not(operand) => expect(operand, Type::Bool, "!").map(|_| Type::Bool),
+        Node::Negate(operand) => expect(operand, Type::Number, "-").map(|_| Type::Number),
+        Node::Binary(op, left, right) => {
+            let symbol = op_symbol(op);
+            match op {
+                BinaryOp::And | BinaryOp::Or => {
+                    expect(left, Type::Bool, symbol)?;
+                    expect(right, Type::Bool, symbol)?;
+                    Ok(Type::Bool)
+                }
+                BinaryOp::Eq | BinaryOp::Ne => {
+                    let left_type = check(left)?;
+                    expect(right, left_type, symbol)?;
+                    Ok(Type::Bool)
+                }
+                BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => {
+                    expect(left, Type::Number, symbol)?;
+                    expect(right, Type::Number, symbol)?;
+                    Ok(Type::Bool)
+                }
+                BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div => {
+                    expect(left, Type::Number, symbol)?;
+                    expect(right, Type::Number, symbol)?;
+                    Ok(Type::Number)
+                }
+            }
+        }
+    }
+}
+
+fn op_symbol(op: &BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::And => "&&",
+        BinaryOp::Or => "||",
+        BinaryOp::Eq => "==",
+        BinaryOp::Ne => "!=",
+        BinaryOp::Lt => "<",
+        BinaryOp::Le => "<=",
+        BinaryOp::Gt => ">",
+        BinaryOp::Ge => ">=",
+        BinaryOp::Add => "+",
+        BinaryOp::Sub => "-",
+        BinaryOp::Mul => "*",
+        BinaryOp::Div => "/",
+    }
+}
+
+// Evaluate a type checked expression; comparisons with missing values are false
+fn eval(node: &Node, variables: &[(String, Value)]) -> Value {
+    match node {
+        Node::Literal(value) => value.clone(),
+        Node::Variable(name) => variables
+            .iter()
+            .find(|(variable, _)| variable == name)
+            .map_or(Value::Missing, |(_, value)| value.clone()),
+        Node::Not(operand) => match eval(operand, variables) {
+            Value::Bool(value) => Value::Bool(!value),
+            _ => Value::Missing,
+        },
+        Node::Negate(operand) => match eval(operand, variables) {
+            Value::Number(value) => Value::Number(-value),
+            _ => Value::Missing,
+        },
+        Node::Binary(BinaryOp::And, left, right) => {
+            // Short-circuit evaluation
+            if eval(left, variables) != Value::Bool(true) {
+                return Value::Bool(false);
+            }
+            Value::Bool(eval(right, variables) == Value::Bool(true))
+        }
+        Node::Binary(BinaryOp::Or, left, right) => {
+            if eval(left, variables) == Value::Bool(true) {
+                return Value::Bool(true);
+            }
+            Value::Bool(eval(right, variables) == Value::Bool(true))
+        }
+        Node::Binary(op, left, right) => {
+            let (left, right) = (eval(left, variables), eval(right, variables));
+            if left == Value::Missing || right == Value::Missing {
+                return match op {
+                    BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div => Value::Missing,
+                    _ => Value::Bool(false),
+                };
+            }
+            match (op, left, right) {
+                (BinaryOp::Eq, left, right) => Value::Bool(left == right),
+                (BinaryOp::Ne, left, right) => Value::Bool(left != right),
+                (op, Value::Number(left), Value::Number(right)) => match op {
+                    BinaryOp::Lt => Value::Bool(left < right),
+                    BinaryOp::Le => Value::Bool(left <= right),
+                    BinaryOp::Gt => Value::Bool(left > right),
+                    BinaryOp::Ge => Value::Bool(left >= right),
+                    BinaryOp::Add => Value::Number(left + right),
+                    BinaryOp::Sub => Value::Number(left - right),
+                    BinaryOp::Mul => Value::Number(left * right),
+                    BinaryOp::Div => Value::Number(left / right),
+                    _ => Value::Missing,
+                },
+                _ => Value::Missing,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::template::FormatObject;
+
+    const FORMAT_OBJ: FormatObject = FormatObject {
+        percentage: &25.0,
+        energy_rate: &22.5,
+        energy: &13.0,
+        temperature: "n/a",
+        health: &91.5,
+        energy_full: &52.0,
+        energy_full_design: &57.0,
+        cycle_count: "312",
+        countdown: &0,
+        drop: &0.0,
+        drop_rate: &0.0,
+        state: "discharging",
+        time_to_empty: "95",
+        serial: "31415",
+    };
+
+    fn evaluate(source: &str) -> bool {
+        Expression::parse(source).unwrap().evaluate(&FORMAT_OBJ)
+    }
+
+    #[test]
+    fn test_evaluate_comparisons() {
+        assert!(evaluate(
+            "percentage < 30 && energy_rate > 20 && state == \"discharging\""
+        ));
+        assert!(!evaluate("percentage < 20 || state == 'charging'"));
+        assert!(evaluate("cycle_count >= 312"));
+        assert!(evaluate("!(health > 95)"));
+        assert!(evaluate("state != \"full\""));
+    }
+
+    #[test]
+    fn test_evaluate_arithmetic() {
+        assert!(evaluate("energy_full / energy_full_design * 100 < 92"));
+        assert!(evaluate("-energy_rate < 0"));
+        assert!(evaluate("percentage + 5 * 2 == 35"));
+        assert!(evaluate("(percentage + 5) * 2 == 60"));
+    }
+
+    #[test]
+    fn test_evaluate_missing_value() {
+        // Temperature is not reported, so any comparison is false
+        assert!(!evaluate("temperature > 50"));
+        assert!(!evaluate("temperature <= 50"));
+        assert!(!evaluate("temperature + 1 > 0"));
+        assert!(evaluate("temperature > 50 || percentage < 30"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let test_values = [
+            ("percentage <", "Unexpected end of expression"),
+            ("(percentage < 30", "Missing closing parenthesis"),
+            ("state == \"discharging", "Missing closing quote"),
+            ("percentage < 30 30", "Unexpected token Number(30.0)"),
+            ("percentage ~ 30", "Unexpected character '~'"),
+            ("1.2.3 > 0", "Invalid number '1.2.3'"),
+        ];
+        for (source, message) in test_values {
+            let result = Expression::parse(source);
+            assert_eq!(result.unwrap_err(), message);
+        }
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let message = "Expression nested deeper than 64 levels";
+        let nested = |depth: usize| format!("{}true{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(Expression::parse(&nested(MAX_DEPTH)).is_ok());
+        assert_eq!(Expression::parse(&nested(10_000)).unwrap_err(), message);
+
+        let negated = format!("{}true", "!".repeat(10_000));
+        assert_eq!(Expression::parse(&negated).unwrap_err(), message);
+
+        let sum = format!("{}1 > 0", "1 + ".repeat(10_000));
+        assert_eq!(Expression::parse(&sum).unwrap_err(), message);
+        let sum = format!("{}1 > 0", "1 + ".repeat(MAX_DEPTH));
+        assert!(Expression::parse(&sum).is_ok());
+    }
+
+    #[test]
+    fn test_type_errors() {
+        let test_values = [
+            ("voltage > 12", "Unknown variable 'voltage'"),
+            (
+                "countdown > 0",
+                "Variable 'countdown' is not available in expressions",
+            ),
+            ("state > 1", "'>' expects number, found text"),
+            ("state == 1", "'==' expects text, found number"),
+            ("percentage && true", "'&&' expects bool, found number"),
+            ("!percentage", "'!' expects bool, found number"),
+            (
+                "percentage + 1",
+                "Expression must evaluate to bool, found number",
+            ),
+        ];
+        for (source, message) in test_values {
+            let result = Expression::parse(source);
+            assert_eq!(result.unwrap_err(), message);
+        }
+    }
+
+    #[test]
+    fn test_variables_match_template() {
+        // Every template placeholder has to be usable in expressions
+        let template = FORMAT_OBJ.to_template();
+        for line in template.lines() {
+            let (name, _) = line.split_once(": ").unwrap();
+            assert!(variable_type(name).is_some(), "missing variable '{}'", name);
+        }
+        assert_eq!(template.lines().count(), VARIABLES.len());
+    }
+}
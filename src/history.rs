@@ -0,0 +1,116 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// Rolling window of recent battery levels
+#[derive(Debug)]
+pub struct ChargeHistory {
+    readings: VecDeque<(Instant, f32)>,
+    max_age: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChargeDrop {
+    // Drop of battery level, expressed as decimal between 0 and 1
+    pub drop: f32,
+    // Drop of battery level per minute, expressed as decimal between 0 and 1
+    pub per_minute: f32,
+}
+
+impl ChargeHistory {
+    pub fn new(max_age: Duration) -> ChargeHistory {
+        ChargeHistory {
+            readings: VecDeque::new(),
+            max_age,
+        }
+    }
+
+    pub fn record(&mut self, now: Instant, charge_value: f32) {
+        self.readings.push_back((now, charge_value));
+        while let Some((time, _)) = self.readings.front() {
+            if now.duration_since(*time) <= self.max_age {
+                break;
+            }
+            self.readings.pop_front();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.readings.clear();
+    }
+
+    // Largest drop from any reading within `window` to the latest reading
+    pub fn drop_within(&self, window: Duration, now: Instant) -> Option<ChargeDrop> {
+        let (_, latest) = self.readings.back()?;
+        let (peak_time, peak) = self
+            .readings
+            .iter()
+            .filter(|(time, _)| now.duration_since(*time) <= window)
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))?;
+        let drop = (peak - latest).max(0.0);
+        let minutes = now.duration_since(*peak_time).as_secs_f32() / 60.0;
+        let per_minute = if minutes > 0.0 { drop / minutes } else { 0.0 };
+        Some(ChargeDrop { drop, per_minute })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drop_within_window() {
+        let start = Instant::now();
+        let mut history = ChargeHistory::new(Duration::from_secs(600));
+        history.record(start, 0.32);
+        history.record(start + Duration::from_secs(60), 0.30);
+        history.record(start + Duration::from_secs(300), 0.08);
+
+        let now = start + Duration::from_secs(300);
+        let observed = history.drop_within(Duration::from_secs(600), now).unwrap();
+        assert!((observed.drop - 0.24).abs() < 1e-6);
+        assert!((observed.per_minute - 0.048).abs() < 1e-6);
+
+        // Only readings within the window are taken into account
+        let observed = history.drop_within(Duration::from_secs(240), now).unwrap();
+        assert!((observed.drop - 0.22).abs() < 1e-6);
+        assert!((observed.per_minute - 0.055).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_rising_charge_is_no_drop() {
+        let start = Instant::now();
+        let mut history = ChargeHistory::new(Duration::from_secs(600));
+        history.record(start, 0.5);
+        history.record(start + Duration::from_secs(60), 0.6);
+
+        let observed = history
+            .drop_within(Duration::from_secs(600), start + Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(observed.drop, 0.0);
+    }
+
+    #[test]
+    fn test_old_readings_are_discarded() {
+        let start = Instant::now();
+        let mut history = ChargeHistory::new(Duration::from_secs(120));
+        history.record(start, 0.9);
+        history.record(start + Duration::from_secs(60), 0.5);
+        history.record(start + Duration::from_secs(181), 0.4);
+        assert_eq!(history.readings.len(), 1);
+    }
+
+    #[test]
+    fn test_empty_history() {
+        let mut history = ChargeHistory::new(Duration::from_secs(120));
+        assert_eq!(
+            history.drop_within(Duration::from_secs(60), Instant::now()),
+            None
+        );
+        history.record(Instant::now(), 0.5);
+        history.clear();
+        assert_eq!(
+            history.drop_within(Duration::from_secs(60), Instant::now()),
+            None
+        );
+    }
+}
@@ -0,0 +1,246 @@
+use crate::config::SystemAction;
+use anyhow::{bail, Context, Result};
+use zbus::blocking::Connection;
+
+const DESTINATION: &str = "org.freedesktop.login1";
+const MANAGER_PATH: &str = "/org/freedesktop/login1";
+const MANAGER_INTERFACE: &str = "org.freedesktop.login1.Manager";
+// Session of battered, or the graphical session of its user if battered runs outside of one
+const SESSION_PATH: &str = "/org/freedesktop/login1/session/auto";
+const SESSION_INTERFACE: &str = "org.freedesktop.login1.Session";
+
+// Client of systemd-logind, taking system actions without running a command
+pub struct Logind {
+    connection: Connection,
+}
+
+impl Logind {
+    pub fn system() -> Result<Logind> {
+        let connection = Connection::system().context("Failed to connect to the system bus")?;
+        Ok(Logind::new(connection))
+    }
+
+    pub fn new(connection: Connection) -> Logind {
+        Logind { connection }
+    }
+
+    // Take the action, or the fallback if logind can't take it, e.g. hibernate without swap
+    pub fn take(
+        &self,
+        action: SystemAction,
+        fallback: Option<SystemAction>,
+    ) -> Result<SystemAction> {
+        let answer = self.can(action)?;
+        if answer == "yes" {
+            info!("Taking system action '{}'", action);
+            self.call(action)?;
+            return Ok(action);
+        }
+        let Some(fallback) = fallback else {
+            bail!("Cannot {}: logind answered '{}'", action, answer);
+        };
+        warn!(
+            "Cannot {}: logind answered '{}'; falling back to {}",
+            action, answer, fallback
+        );
+        self.take(fallback, None)
+    }
+
+    // Whether logind can take the action: "yes", "no", "challenge" or "na"
+    fn can(&self, action: SystemAction) -> Result<String> {
+        let method = match action {
+            SystemAction::Suspend => "CanSuspend",
+            SystemAction::Hibernate => "CanHibernate",
+            SystemAction::HybridSleep => "CanHybridSleep",
+            SystemAction::Poweroff => "CanPowerOff",
+            SystemAction::Lock => return Ok(String::from("yes")), // Nothing to check
+        };
+        let reply = self
+            .connection
+            .call_method(
+                Some(DESTINATION),
+                MANAGER_PATH,
+                Some(MANAGER_INTERFACE),
+                method,
+                &(),
+            )
+            .with_context(|| format!("Failed to call {} of logind", method))?;
+        reply
+            .body()
+            .deserialize()
+            .with_context(|| format!("Unexpected reply to {} of logind", method))
+    }
+
+    fn call(&self, action: SystemAction) -> Result<()> {
+        let method = match action {
+            SystemAction::Suspend => "Suspend",
+            SystemAction::Hibernate => "Hibernate",
+            SystemAction::HybridSleep => "HybridSleep",
+            SystemAction::Poweroff => "PowerOff",
+            SystemAction::Lock => {
+                self.connection
+                    .call_method(
+                        Some(DESTINATION),
+                        SESSION_PATH,
+                        Some(SESSION_INTERFACE),
+                        "Lock",
+                        &(),
+                    )
+                    .context("Failed to call Lock of logind")?;
+                return Ok(());
+            }
+        };
+        // Not interactive, as there is nobody to ask for authentication
+        self.connection
+            .call_method(
+                Some(DESTINATION),
+                MANAGER_PATH,
+                Some(MANAGER_INTERFACE),
+                method,
+                &(false,),
+            )
+            .with_context(|| format!("Failed to call {} of logind", method))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixStream;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use zbus::blocking::connection::Builder;
+    use zbus::{fdo, interface, Guid};
+
+    // Methods called on the mock, with their arguments
+    type Calls = Arc<Mutex<Vec<String>>>;
+
+    struct MockManager {
+        can_hibernate: &'static str,
+        calls: Calls,
+    }
+
+    #[interface(name = "org.freedesktop.login1.Manager")]
+    impl MockManager {
+        fn can_suspend(&self) -> &str {
+            "yes"
+        }
+        fn can_hibernate(&self) -> &str {
+            self.can_hibernate
+        }
+        fn can_hybrid_sleep(&self) -> &str {
+            self.can_hibernate
+        }
+        fn can_power_off(&self) -> &str {
+            "challenge"
+        }
+        fn suspend(&self, interactive: bool) {
+            self.record(format!("Suspend({})", interactive));
+        }
+        fn hibernate(&self, interactive: bool) {
+            self.record(format!("Hibernate({})", interactive));
+        }
+        fn hybrid_sleep(&self, interactive: bool) {
+            self.record(format!("HybridSleep({})", interactive));
+        }
+        fn power_off(&self, _interactive: bool) -> fdo::Result<()> {
+            Err(fdo::Error::AccessDenied(String::from("Not allowed")))
+        }
+    }
+
+    impl MockManager {
+        fn record(&self, call: String) {
+            self.calls.lock().unwrap().push(call);
+        }
+    }
+
+    struct MockSession {
+        calls: Calls,
+    }
+
+    #[interface(name = "org.freedesktop.login1.Session")]
+    impl MockSession {
+        fn lock(&self) {
+            self.calls.lock().unwrap().push(String::from("Lock"));
+        }
+    }
+
+    // Mock logind on a private connection, returning its client and the calls it received
+    fn mock_logind(can_hibernate: &'static str) -> (Logind, Connection, Calls) {
+        let calls = Calls::default();
+        let manager = MockManager {
+            can_hibernate,
+            calls: Arc::clone(&calls),
+        };
+        let session = MockSession {
+            calls: Arc::clone(&calls),
+        };
+        let (server, client) = UnixStream::pair().unwrap();
+        let server = thread::spawn(move || {
+            Builder::unix_stream(server)
+                .server(Guid::generate())
+                .unwrap()
+                .p2p()
+                .serve_at(MANAGER_PATH, manager)
+                .unwrap()
+                .serve_at(SESSION_PATH, session)
+                .unwrap()
+                .build()
+                .unwrap()
+        });
+        let client = Builder::unix_stream(client).p2p().build().unwrap();
+        (Logind::new(client), server.join().unwrap(), calls)
+    }
+
+    #[test]
+    fn test_take_system_action() {
+        let (logind, _server, calls) = mock_logind("yes");
+        let taken = logind.take(SystemAction::Hibernate, None).unwrap();
+        assert_eq!(taken, SystemAction::Hibernate);
+        assert_eq!(*calls.lock().unwrap(), vec!["Hibernate(false)"]);
+    }
+
+    #[test]
+    fn test_fallback_if_unavailable() {
+        let (logind, _server, calls) = mock_logind("na");
+        let taken = logind
+            .take(SystemAction::HybridSleep, Some(SystemAction::Suspend))
+            .unwrap();
+        assert_eq!(taken, SystemAction::Suspend);
+        assert_eq!(*calls.lock().unwrap(), vec!["Suspend(false)"]);
+
+        // Without a fallback, nothing happens
+        let result = logind.take(SystemAction::Hibernate, None);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Cannot hibernate: logind answered 'na'"
+        );
+        assert_eq!(calls.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_lock() {
+        let (logind, _server, calls) = mock_logind("yes");
+        logind.take(SystemAction::Lock, None).unwrap();
+        assert_eq!(*calls.lock().unwrap(), vec!["Lock"]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let (logind, _server, calls) = mock_logind("yes");
+        // Authentication would be needed
+        let result = logind.take(SystemAction::Poweroff, None);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Cannot poweroff: logind answered 'challenge'"
+        );
+
+        let result = logind.call(SystemAction::Poweroff);
+        assert_eq!(
+            format!("{:#}", result.unwrap_err()),
+            "Failed to call PowerOff of logind: org.freedesktop.DBus.Error.AccessDenied: Not allowed"
+        );
+        assert!(calls.lock().unwrap().is_empty());
+    }
+}
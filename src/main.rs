@@ -1,226 +1,906 @@
+mod ac;
+mod clock;
 mod config;
+mod debounce;
+mod expr;
+mod history;
+mod logind;
+mod process;
+mod state;
 mod template;
 
 #[macro_use]
 extern crate log;
 extern crate starship_battery;
+use ac::{adapter_or_state_on_ac, state_on_ac, AcTracker, AcTransition, POWER_SUPPLY_PATH};
 use anyhow::{Context, Result};
-use config::{xdg_config_home, Action, Config, OnAcAction};
+use chrono::NaiveDateTime;
+use clock::{Clock, SystemClock};
+use config::{
+    is_scheduled, parse_config, xdg_config_home, xdg_state_home, AcSource, Action, Command,
+    CommandMode, Config, DropAction, Event, HealthAction, Notify, OnFailure, PowerDrawAction,
+    TemperatureAction, Trigger,
+};
+use debounce::Debouncer;
+use history::ChargeHistory;
+use logind::Logind;
 use notify_rust::{Notification, Urgency};
+use process::{CommandState, Output, Process};
+use starship_battery::units::energy::watt_hour;
+use starship_battery::units::power::watt;
+use starship_battery::units::thermodynamic_temperature::degree_celsius;
+use starship_battery::units::time::minute;
 use starship_battery::{Batteries, Battery, State};
-use template::{FormatObject, Template};
+use state::{ModifiedTracker, PersistentState};
+use template::{fill_template, FormatObject, Template};
 
 use std::env;
-use std::path::PathBuf;
-use std::process::Command;
+use std::mem;
+use std::path::{Path, PathBuf};
 use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Delay before the first retry of a failed command, doubling with every retry
+const DEFAULT_RETRY_DELAY: Duration = Duration::from_secs(5);
+// How often to check on running commands, if something waits for them to finish
+const COMMAND_POLL_INTERVAL: Duration = Duration::from_secs(1);
+// Characters of command output shown in a notification
+const MAX_NOTIFY_OUTPUT: usize = 300;
 
 trait CommandRunner {
-    fn run(&mut self) -> Result<()>;
+    fn action(&self) -> &Action;
+    fn action_mut(&mut self) -> &mut Action;
     fn exceeds_threshold(&self, value: &f32) -> bool;
-}
+    // Kind of the trigger or its event, for `BATTERED_TRIGGER`
+    fn trigger(&self) -> String;
+    // Threshold in the unit of its placeholder, for `BATTERED_THRESHOLD`
+    fn threshold_value(&self) -> Option<f32>;
 
-impl CommandRunner for Action {
-    fn run(&mut self) -> Result<()> {
-        let command = self.command.as_ref();
-        match command {
-            Some(cmd) => {
-                let status = Command::new(&cmd[0])
-                    .args(&cmd[1..])
-                    .status()
-                    .with_context(|| format!("Failed to execute '{}'", cmd.join(" ")))?;
-                if !status.success() {
-                    return Err(anyhow::anyhow!("Command failed: {}", status));
-                }
-                Ok(())
+    fn name(&self) -> Option<&str> {
+        self.action().name.as_deref()
+    }
+
+    fn command_state(&mut self) -> &mut CommandState {
+        &mut self.action_mut().command_state
+    }
+
+    // `command` runs before `commands`, if both are set
+    fn all_commands(&self) -> Vec<&Command> {
+        let action = self.action();
+        action.command.iter().chain(&action.commands).collect()
+    }
+
+    // Environment of the command, exposing the same values as the placeholders
+    fn env(&self, format_obj: &FormatObject) -> Vec<(String, String)> {
+        let mut env = format_obj.to_env();
+        env.push((String::from("BATTERED_TRIGGER"), self.trigger()));
+        if let Some(threshold) = self.threshold_value() {
+            env.push((String::from("BATTERED_THRESHOLD"), threshold.to_string()));
+        }
+        if let Some(name) = self.name() {
+            env.push((String::from("BATTERED_NAME"), name.to_string()));
+        }
+        env
+    }
+
+    // Start commands in the background, their result is picked up by `poll`
+    fn run(&mut self, format_obj: &FormatObject) -> Result<()> {
+        let system = self.action().system;
+        let state = self.command_state();
+        state.retries = 0;
+        state.retry_at = None;
+        state.system = system; // Retries only run the commands again
+        self.spawn(format_obj)
+    }
+
+    fn spawn(&mut self, format_obj: &FormatObject) -> Result<()> {
+        if let Some(process) = self.command_state().processes.first() {
+            warn!("Command '{}' is still running", process.command());
+            return Ok(());
+        }
+        let action = self.action();
+        // Placeholders are filled in after splitting, so values can't inject arguments
+        let queue = self
+            .all_commands()
+            .iter()
+            .map(|cmd| cmd.args(action.shell, |arg| fill_template(arg, format_obj)))
+            .collect();
+        let mut env = self.env(format_obj);
+        env.extend(
+            action
+                .env
+                .iter()
+                .map(|(key, value)| (key.clone(), fill_template(value, format_obj))),
+        );
+        let state = self.command_state();
+        state.queue = queue;
+        state.env = env;
+        state.errors.clear();
+        state.output = None;
+        self.start_commands();
+        Ok(())
+    }
+
+    // Start queued commands, in sequential mode only once the previous one finished
+    fn start_commands(&mut self) {
+        let action = self.action_mut();
+        let state = &mut action.command_state;
+        loop {
+            if action.stop_on_failure && !state.errors.is_empty() {
+                state.queue.clear();
+            }
+            if action.mode == CommandMode::Sequential && !state.processes.is_empty() {
+                break;
+            }
+            let Some(args) = state.queue.pop_front() else {
+                break;
+            };
+            match Process::spawn(&args, &state.env, action.cwd.as_deref(), action.timeout) {
+                Ok(process) => state.processes.push(process),
+                Err(e) => state.errors.push(e),
             }
-            _ => Ok(()),
         }
     }
 
-    fn exceeds_threshold(&self, value: &f32) -> bool {
-        value < &self.percentage
+    // Returns when to check again on running commands, once all finished whether any failed
+    fn poll(&mut self, now: Instant) -> Result<Option<Duration>> {
+        for mut process in std::mem::take(&mut self.command_state().processes) {
+            let Some(result) = process.poll(now) else {
+                self.command_state().processes.push(process);
+                continue;
+            };
+            let output = process.output();
+            self.log_output(&process, &output);
+            let state = self.command_state();
+            state.output.get_or_insert_default().append(output);
+            state.errors.extend(result.err());
+        }
+        let action = self.action_mut();
+        let state = &mut action.command_state;
+        if action.stop_on_failure && !state.errors.is_empty() {
+            for process in state.processes.iter_mut() {
+                process.stop(now);
+            }
+        }
+        self.start_commands();
+        let action = self.action_mut();
+        let state = &mut action.command_state;
+        // System action comes last, so that commands can e.g. save work before suspending
+        if let Some(system) = state.take_system() {
+            let result =
+                Logind::system().and_then(|logind| logind.take(system, action.system_fallback));
+            state.errors.extend(result.err());
+        }
+        if state.is_running() {
+            let next_poll = state
+                .processes
+                .iter()
+                .filter_map(|p| p.next_poll(now))
+                .min();
+            if state.queue.is_empty() {
+                return Ok(next_poll);
+            }
+            // Start the next command soon after the previous one finished
+            return Ok(next_poll.into_iter().chain([COMMAND_POLL_INTERVAL]).min());
+        }
+        match state.errors.len() {
+            0 => Ok(None),
+            1 => Err(state.errors.remove(0)),
+            failed => {
+                let errors: Vec<String> =
+                    state.errors.drain(..).map(|e| format!("{:#}", e)).collect();
+                Err(anyhow::anyhow!(
+                    "{} commands failed: {}",
+                    failed,
+                    errors.join("; ")
+                ))
+            }
+        }
+    }
+
+    // Log result and output of a finished command, so it has some context in the journal
+    fn log_output(&self, process: &Process, output: &Output) {
+        let label = match self.name() {
+            Some(name) => format!("Command '{}' of action '{}'", process.command(), name),
+            None => format!("Command '{}'", process.command()),
+        };
+        match process.status() {
+            Some(status) => info!("{} finished ({})", label, status),
+            None => info!("{} finished", label),
+        }
+        for line in output.stdout.lines() {
+            info!("{}: {}", label, line);
+        }
+        for line in output.stderr.lines() {
+            warn!("{}: {}", label, line);
+        }
     }
 }
 
-impl CommandRunner for OnAcAction {
-    fn run(&mut self) -> Result<()> {
-        let command = self.command.as_ref();
-        match command {
-            Some(cmd) => {
-                let status = Command::new(&cmd[0])
-                    .args(&cmd[1..])
-                    .status()
-                    .with_context(|| format!("Failed to execute '{}'", cmd.join(" ")))?;
-                if !status.success() {
-                    return Err(anyhow::anyhow!("Command failed: {}", status));
-                }
-                Ok(())
-            }
-            _ => Ok(()),
+impl CommandRunner for Trigger {
+    fn action(&self) -> &Action {
+        &self.action
+    }
+
+    fn action_mut(&mut self) -> &mut Action {
+        &mut self.action
+    }
+
+    fn exceeds_threshold(&self, value: &f32) -> bool {
+        match (self.on, self.threshold()) {
+            (Event::Below, Some(threshold)) => value < &threshold,
+            (Event::Above, Some(threshold)) => value > &threshold,
+            (Event::Below | Event::Above, None) => false,
+            // Other events only take the battery level into account if a percentage is set
+            (_, Some(threshold)) => value >= &threshold,
+            (_, None) => true,
         }
     }
 
+    fn trigger(&self) -> String {
+        self.on.to_string()
+    }
+
+    fn threshold_value(&self) -> Option<f32> {
+        self.threshold().map(percent) // Like $percentage
+    }
+}
+
+impl CommandRunner for PowerDrawAction {
+    fn action(&self) -> &Action {
+        &self.action
+    }
+
+    fn action_mut(&mut self) -> &mut Action {
+        &mut self.action
+    }
+
+    fn exceeds_threshold(&self, value: &f32) -> bool {
+        value > &self.watts
+    }
+
+    fn trigger(&self) -> String {
+        String::from("power_draw")
+    }
+
+    fn threshold_value(&self) -> Option<f32> {
+        Some(self.watts)
+    }
+}
+
+impl CommandRunner for DropAction {
+    fn action(&self) -> &Action {
+        &self.action
+    }
+
+    fn action_mut(&mut self) -> &mut Action {
+        &mut self.action
+    }
+
+    fn exceeds_threshold(&self, value: &f32) -> bool {
+        value > &self.by
+    }
+
+    fn trigger(&self) -> String {
+        String::from("drop")
+    }
+
+    fn threshold_value(&self) -> Option<f32> {
+        Some(percent(self.by)) // Like $drop
+    }
+}
+
+impl CommandRunner for TemperatureAction {
+    fn action(&self) -> &Action {
+        &self.action
+    }
+
+    fn action_mut(&mut self) -> &mut Action {
+        &mut self.action
+    }
+
+    fn exceeds_threshold(&self, value: &f32) -> bool {
+        value > &self.above
+    }
+
+    fn trigger(&self) -> String {
+        String::from("temperature")
+    }
+
+    fn threshold_value(&self) -> Option<f32> {
+        Some(self.above)
+    }
+}
+
+impl CommandRunner for HealthAction {
+    fn action(&self) -> &Action {
+        &self.action
+    }
+
+    fn action_mut(&mut self) -> &mut Action {
+        &mut self.action
+    }
+
     fn exceeds_threshold(&self, value: &f32) -> bool {
-        value >= &self.percentage
+        self.below.is_some_and(|below| value < &below)
+    }
+
+    fn trigger(&self) -> String {
+        String::from("health")
+    }
+
+    fn threshold_value(&self) -> Option<f32> {
+        self.below.map(percent) // Like $health
     }
 }
 
-trait DesktopNotification {
-    fn show(&mut self, format_obj: &FormatObject);
-    fn has_notify(&self) -> bool;
-    fn fill_template<T: Template>(&self, input_string: String, format_obj: &T) -> String;
+// Decimal between 0 and 1 in percent, rounded like the placeholders
+fn percent(value: f32) -> f32 {
+    (value * 1000.0).round() / 10.0
 }
 
-impl DesktopNotification for Action {
-    fn show(&mut self, format_obj: &FormatObject) {
-        if let Some(n) = &self.notify {
-            let templated_summary = &self.fill_template(n.summary.clone(), format_obj);
-            let mut body = n.body.clone().unwrap_or(String::from(""));
-            body = self.fill_template(body, format_obj);
+trait Hysteresis {
+    fn is_armed(&self) -> bool;
+    fn disarm(&mut self);
+    // Returns true if the action was re-armed
+    fn rearm(&mut self, value: &f32) -> bool;
+}
+
+impl Hysteresis for Trigger {
+    fn is_armed(&self) -> bool {
+        !self.fired
+    }
+
+    fn disarm(&mut self) {
+        self.fired = self.hysteresis.is_some();
+    }
+
+    fn rearm(&mut self, value: &f32) -> bool {
+        let (Some(percentage), true) = (self.threshold(), self.fired) else {
+            return false;
+        };
+        let hysteresis = self.hysteresis.unwrap_or_default();
+        let rearmed = match self.on {
+            Event::Below => self.hysteresis.is_some() && value >= &(percentage + hysteresis),
+            Event::Above => value <= &(percentage - hysteresis),
+            _ => false,
+        };
+        if rearmed {
+            self.fired = false;
+        }
+        rearmed
+    }
+}
+
+trait Repeat {
+    fn repeat_due(&self, now: Instant) -> bool;
+    fn repeats_command(&self) -> bool;
+    fn mark_taken(&mut self, now: Instant);
+    fn mark_repeated(&mut self, now: Instant);
+}
+
+impl Repeat for Trigger {
+    fn repeat_due(&self, now: Instant) -> bool {
+        let (Some(repeat), Some(last_taken)) = (self.repeat, self.last_taken) else {
+            return false;
+        };
+        now.duration_since(last_taken) >= repeat
+            && self
+                .repeat_limit
+                .is_none_or(|limit| self.repeat_count < limit)
+    }
+
+    fn repeats_command(&self) -> bool {
+        self.repeat_command
+    }
+
+    fn mark_taken(&mut self, now: Instant) {
+        self.last_taken = Some(now);
+        self.repeat_count = 0;
+    }
+
+    fn mark_repeated(&mut self, now: Instant) {
+        self.last_taken = Some(now);
+        self.repeat_count += 1;
+    }
+}
+
+trait Countdown {
+    // Returns true if a countdown was started instead of running the command right away
+    fn start_countdown(&mut self, now: Instant) -> bool;
+    fn countdown_remaining(&self, now: Instant) -> Option<Duration>;
+    fn countdown_interval(&self) -> Duration;
+    fn finish_countdown(&mut self);
+    fn cancel_countdown(&mut self);
+}
+
+impl Countdown for Trigger {
+    fn start_countdown(&mut self, now: Instant) -> bool {
+        self.countdown_deadline = self.countdown.map(|countdown| now + countdown);
+        self.countdown_deadline.is_some()
+    }
+
+    fn countdown_remaining(&self, now: Instant) -> Option<Duration> {
+        self.countdown_deadline
+            .map(|deadline| deadline.saturating_duration_since(now))
+    }
+
+    fn countdown_interval(&self) -> Duration {
+        self.countdown_interval.max(Duration::from_secs(1))
+    }
+
+    fn finish_countdown(&mut self) {
+        self.countdown_deadline = None;
+    }
+
+    fn cancel_countdown(&mut self) {
+        if self.countdown_deadline.is_none() {
+            return;
+        }
+        // Replace countdown notification, so it doesn't linger
+        if let Some(id) = self.notification_id {
             Notification::new()
-                .summary(templated_summary)
-                .body(body.as_str())
-                .icon(n.icon.as_str())
-                .urgency(n.urgency)
-                .timeout(n.timeout)
+                .summary("Countdown cancelled")
+                .body("Power supply connected")
+                .icon("battery-good-charging")
+                .urgency(Urgency::Low)
+                .id(id)
                 .show()
                 .ok();
         }
+        self.countdown_deadline = None;
     }
+}
 
-    fn has_notify(&self) -> bool {
-        self.notify.is_some()
+trait Exclusive {
+    // Exclusive actions are skipped if the battery level drops past them between checks
+    fn is_exclusive(&self) -> bool;
+}
+
+impl Exclusive for Trigger {
+    fn is_exclusive(&self) -> bool {
+        self.exclusive.unwrap_or(true)
+    }
+}
+
+trait Toggle {
+    fn is_enabled(&self) -> bool;
+}
+
+impl<T: CommandRunner + ?Sized> Toggle for T {
+    fn is_enabled(&self) -> bool {
+        self.action().is_enabled()
     }
+}
 
-    fn fill_template<T: Template>(&self, input_string: String, format_obj: &T) -> String {
-        let mut result = input_string;
-        let format_string = format_obj.to_template();
+trait Schedule {
+    fn is_scheduled(&self, now: &NaiveDateTime) -> bool;
+}
 
-        // Replace template vars with templated values from FormatObject
-        for line in format_string.lines() {
-            let parts: Vec<&str> = line.split(": ").collect();
-            if parts.len() == 2 {
-                let placeholder = format!("${}", parts[0]);
-                result = result.replace(&placeholder, parts[1]);
-            }
-        }
-        result
+impl<T: CommandRunner + ?Sized> Schedule for T {
+    fn is_scheduled(&self, now: &NaiveDateTime) -> bool {
+        let action = self.action();
+        is_scheduled(&action.active_hours, &action.days, now)
+    }
+}
+
+trait Condition {
+    fn condition_met(&self, format_obj: &FormatObject) -> bool;
+}
+
+impl<T: CommandRunner + ?Sized> Condition for T {
+    fn condition_met(&self, format_obj: &FormatObject) -> bool {
+        self.action()
+            .when
+            .as_ref()
+            .is_none_or(|when| when.evaluate(format_obj))
     }
 }
 
-impl DesktopNotification for OnAcAction {
+trait DesktopNotification: CommandRunner {
+    fn notify(&self) -> Option<&Notify> {
+        self.action().notify.as_ref()
+    }
+
     fn show(&mut self, format_obj: &FormatObject) {
-        if let Some(n) = &self.notify {
-            let templated_summary = &self.fill_template(n.summary.clone(), format_obj);
-            let mut body = n.body.clone().unwrap_or(String::from(""));
-            body = self.fill_template(body, format_obj);
-            Notification::new()
-                .summary(templated_summary)
-                .body(body.as_str())
-                .icon(n.icon.as_str())
-                .urgency(n.urgency)
-                .timeout(n.timeout)
-                .show()
-                .ok();
+        if let Some(notification) = self.notification(format_obj) {
+            notification.show().ok();
         }
     }
 
     fn has_notify(&self) -> bool {
-        self.notify.is_some()
+        self.notify().is_some()
     }
 
-    fn fill_template<T: Template>(&self, input_string: String, format_obj: &T) -> String {
-        let mut result = input_string;
-        let format_string = format_obj.to_template();
+    // Output of the last run of the command, for `$command_output`
+    fn command_output(&self) -> &str {
+        self.action()
+            .command_state
+            .output
+            .as_ref()
+            .map_or("", |output| &output.stdout)
+    }
 
-        // Replace template vars with templated values from FormatObject
-        for line in format_string.lines() {
-            let parts: Vec<&str> = line.split(": ").collect();
-            if parts.len() == 2 {
-                let placeholder = format!("${}", parts[0]);
-                result = result.replace(&placeholder, parts[1]);
-            }
+    // Notification showing the output of the command has to wait for it to finish
+    fn shows_command_output(&self) -> bool {
+        self.notify().is_some_and(|n| {
+            n.summary.contains("$command_output")
+                || n.body
+                    .as_ref()
+                    .is_some_and(|body| body.contains("$command_output"))
+        })
+    }
+
+    // Build notification with placeholders filled in
+    fn notification(&self, format_obj: &FormatObject) -> Option<Notification> {
+        let n = self.notify()?;
+        let templated_summary = &self.fill_template(n.summary.clone(), format_obj);
+        let mut body = n.body.clone().unwrap_or(String::from(""));
+        body = self.fill_template(body, format_obj);
+        let mut notification = Notification::new();
+        notification
+            .summary(templated_summary)
+            .body(body.as_str())
+            .icon(n.icon.as_str())
+            .urgency(n.urgency)
+            .timeout(n.timeout);
+        Some(notification)
+    }
+
+    fn fill_template(&self, input_string: String, format_obj: &FormatObject) -> String {
+        // Filled in last, so placeholders in the output are left alone
+        fill_template(&input_string, format_obj).replace(
+            "$command_output",
+            &truncate(self.command_output(), MAX_NOTIFY_OUTPUT),
+        )
+    }
+}
+
+// Shorten text to a number of characters, marking that something was cut off
+fn truncate(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+impl DesktopNotification for Trigger {
+    fn show(&mut self, format_obj: &FormatObject) {
+        let Some(mut notification) = self.notification(format_obj) else {
+            return;
+        };
+        if let (Some(_), Some(id)) = (self.countdown_deadline, self.notification_id) {
+            notification.id(id); // Update running countdown in place
+        }
+        if let Ok(handle) = notification.show() {
+            self.notification_id = Some(handle.id());
         }
-        result
     }
 }
 
+impl DesktopNotification for PowerDrawAction {}
+
+impl DesktopNotification for DropAction {}
+
+impl DesktopNotification for TemperatureAction {}
+
+impl DesktopNotification for HealthAction {}
+
 fn get_version_from_env() -> String {
     env!("CARGO_PKG_VERSION").to_string()
 }
 
+#[derive(Debug, PartialEq)]
+enum Cli {
+    Version,
+    // Enable or disable actions by name or tag, persisted in the state file
+    Toggle(Vec<(String, bool)>),
+    // Run battered, enabling or disabling actions for this run only
+    Run(Vec<(String, bool)>),
+}
+
+fn parse_args(args: &[String]) -> Result<Cli> {
+    match args.first().map(String::as_str) {
+        Some("--version" | "-V") => return Ok(Cli::Version),
+        Some(command @ ("enable" | "disable")) => {
+            if args.len() < 2 {
+                return Err(anyhow::anyhow!("Usage: battered {} <name|tag>...", command));
+            }
+            let enabled = command == "enable";
+            let toggles = args[1..].iter().map(|s| (s.clone(), enabled)).collect();
+            return Ok(Cli::Toggle(toggles));
+        }
+        _ => (),
+    }
+    let mut toggles = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let enabled = match arg.as_str() {
+            "--enable" => true,
+            "--disable" => false,
+            _ => return Err(anyhow::anyhow!("Unknown argument '{}'", arg)),
+        };
+        let selector = args
+            .next()
+            .with_context(|| format!("Missing name or tag after '{}'", arg))?;
+        toggles.push((selector.clone(), enabled));
+    }
+    Ok(Cli::Run(toggles))
+}
+
 fn main() -> Result<()> {
     env_logger::init();
 
     // Handle CLI args
-    let args: Vec<String> = env::args().collect();
-    if args.len() > 1 && (args[1] == "--version" || args[1] == "-V") {
-        println!("battered {}", get_version_from_env());
-        return Ok(());
-    }
+    let args: Vec<String> = env::args().skip(1).collect();
+    let state_path = xdg_state_home().join("battered/state.toml");
+    let cli_toggles = match parse_args(&args)? {
+        Cli::Version => {
+            println!("battered {}", get_version_from_env());
+            return Ok(());
+        }
+        Cli::Toggle(toggles) => {
+            // Persist toggles, a running battered picks them up on its next check
+            let mut persistent_state = PersistentState::load(&state_path)?;
+            for (selector, enabled) in toggles {
+                persistent_state.toggles.set(&selector, enabled);
+            }
+            return persistent_state.save(&state_path);
+        }
+        Cli::Run(toggles) => toggles,
+    };
 
     // Config
     let config_path = xdg_config_home().join("battered/config.toml");
-    let config = get_config(&config_path).with_context(|| "Failed to read config")?;
-    let mut actions = config.action;
-    actions.sort_by(|a, b| {
-        a.percentage
-            .partial_cmp(&b.percentage)
-            .expect("Failed to sort actions by percentage")
-    }); // Sort by percentage
+    let mut config = get_config(&config_path).with_context(|| "Failed to read config")?;
+    let on_failure = config.on_failure;
+    for action in config.actions_mut() {
+        action.on_failure = action.on_failure.or(Some(on_failure));
+    }
+    let mut triggers = config.trigger;
+    triggers.extend(config.action);
+    triggers.extend(config.on_ac);
+    // Battery level triggers are matched as a group, all other triggers on their own
+    let (mut actions, mut events): (Vec<Trigger>, Vec<Trigger>) = triggers
+        .into_iter()
+        .partition(|trigger| trigger.on == Event::Below);
+    let mut power_draw = config.power_draw;
+    let mut drop_actions = config.drop;
+    let mut temperature_actions = config.temperature;
+    let mut health_actions = config.health;
+    let mut state_modified = ModifiedTracker::default();
+    state_modified.changed(&state_path);
+    let mut persistent_state = PersistentState::load(&state_path).unwrap_or_else(|e| {
+        warn!("Failed to load state: {:#}", e);
+        PersistentState::default()
+    });
+    for trigger in actions.iter_mut().chain(events.iter_mut()) {
+        trigger.hysteresis = trigger.hysteresis.or(config.hysteresis);
+        trigger.exclusive = trigger.exclusive.or(Some(config.exclusive));
+    }
 
     // Set up battery manager
     let manager = starship_battery::Manager::new()?;
     let mut batteries = manager.batteries()?;
     debug!("Looking for serial number: {:?}", config.serial_number);
     let mut battery = pick_battery(&mut batteries, config.serial_number.as_deref())?;
+    if !temperature_actions.is_empty() && battery.temperature().is_none() {
+        warn!("Battery does not report its temperature; temperature actions will be skipped");
+    }
+    let serial_number = battery
+        .serial_number()
+        .map_or(String::from("n/a"), |s| s.trim().to_string());
+    let mut last_action_index: usize = usize::MAX;
+    resolve_thresholds(&mut actions, &battery);
+    sort_by_threshold(&mut actions, &mut last_action_index);
 
     // Check and act on battery levels
-    let mut last_action_index: usize = usize::MAX;
+    let clock = SystemClock;
+    let mut ac_tracker = AcTracker::default();
+    // Regular battery checks, which are shortened to `debounce` while a change is pending
+    let reading_interval = config
+        .debounce
+        .map_or(config.interval, |d| d.min(config.interval));
+    let mut state_debouncer =
+        Debouncer::new(config.debounce, config.debounce_readings, reading_interval);
+    let mut adapter_debouncer =
+        Debouncer::new(config.debounce, config.debounce_readings, reading_interval);
+    let longest_window = drop_actions.iter().map(|a| a.within).max();
+    let mut charge_history = ChargeHistory::new(longest_window.unwrap_or_default());
+    let mut last_state: Option<State> = None;
     loop {
         manager.refresh(&mut battery)?;
         let charge_value = battery.state_of_charge().value;
         let percentage = (charge_value * 100.0).floor();
-        let state = battery.state();
-        let mut on_ac = config.on_ac.clone();
+        let energy_rate = (battery.energy_rate().get::<watt>() * 10.0).round() / 10.0;
+        let temperature = battery
+            .temperature()
+            .map(|t| (t.get::<degree_celsius>() * 10.0).round() / 10.0);
+        let temperature_value = temperature.map_or(String::from("n/a"), |t| t.to_string());
+        let health = percent(battery.state_of_health().value);
+        let energy_full = (battery.energy_full().get::<watt_hour>() * 10.0).round() / 10.0;
+        let energy_full_design =
+            (battery.energy_full_design().get::<watt_hour>() * 10.0).round() / 10.0;
+        let cycle_count = battery
+            .cycle_count()
+            .map_or(String::from("n/a"), |c| c.to_string());
+        let energy = (battery.energy().get::<watt_hour>() * 10.0).round() / 10.0;
+        let time_to_empty = battery.time_to_empty().map_or(String::from("n/a"), |t| {
+            t.get::<minute>().round().to_string()
+        });
+        let now = clock.now();
+        let state = state_debouncer.update(battery.state(), now);
+        if state != battery.state() {
+            debug!("Ignoring state {} until it holds", battery.state());
+        }
+        let state_value = state.to_string();
+        // Pick up actions enabled or disabled from the command line in the meantime
+        if state_modified.changed(&state_path) {
+            match PersistentState::load(&state_path) {
+                Ok(state) => persistent_state = state,
+                Err(e) => warn!("Failed to load state: {:#}", e),
+            }
+        }
+        let mut toggles = persistent_state.toggles.clone();
+        for (selector, enabled) in &cli_toggles {
+            toggles.set(selector, *enabled);
+        }
+        for runner in all_actions(
+            &mut actions,
+            &mut events,
+            &mut power_draw,
+            &mut drop_actions,
+            &mut temperature_actions,
+            &mut health_actions,
+        ) {
+            let action = runner.action_mut();
+            action.toggled = toggles.lookup(action.selectors());
+        }
+        // Full energy changes as the battery wears, thresholds follow
+        resolve_thresholds(&mut actions, &battery);
+        resolve_thresholds(&mut events, &battery);
+        sort_by_threshold(&mut actions, &mut last_action_index);
         info!("Charge: {:.2}", charge_value);
         info!("State:  {}", state);
+        info!("Rate:   {:.1} W", energy_rate);
+        info!("Temp:   {} °C", temperature_value);
 
         let format_obj = FormatObject {
             percentage: &percentage,
+            energy_rate: &energy_rate,
+            energy: &energy,
+            temperature: &temperature_value,
+            health: &health,
+            energy_full: &energy_full,
+            energy_full_design: &energy_full_design,
+            cycle_count: &cycle_count,
+            countdown: &0,
+            drop: &0.0,
+            drop_rate: &0.0,
+            state: &state_value,
+            time_to_empty: &time_to_empty,
+            serial: &serial_number,
         };
-        if state == State::Charging {
-            if last_action_index != usize::MAX {
-                last_action_index = usize::MAX; // Reset state
-                if let Some(on_ac) = &mut on_ac {
-                    match trigger_action(on_ac, &format_obj) {
-                        Ok(_) => (),
-                        Err(e) => {
-                            // Show notification about failed action
-                            Notification::new()
-                                .summary("Battered action failed")
-                                .body(e.to_string().as_str())
-                                .urgency(Urgency::Critical)
-                                .show()
-                                .ok();
-                            return Err(e);
-                        }
-                    };
-                }
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        if !health_actions.is_empty() && persistent_state.health_check_due(timestamp) {
+            info!("Health: {}% ({} cycles)", health, cycle_count);
+            match_health(
+                &mut health_actions,
+                &battery.state_of_health().value,
+                battery.cycle_count(),
+                &clock,
+                &format_obj,
+            )?;
+            persistent_state.last_health_check = Some(timestamp);
+            if let Err(e) = persistent_state.save(&state_path) {
+                warn!("Failed to save state: {:#}", e);
             }
-            thread::sleep(config.interval);
-            continue; // If the battery is charging there is nothing else to do
         }
-        match_actions(
+        if let Some(temperature) = &temperature {
+            match_temperature(&mut temperature_actions, temperature, &clock, &format_obj)?;
+        }
+        if state != State::Discharging {
+            power_draw.iter_mut().for_each(PowerDrawAction::reset);
+        }
+        let ac_connected = match config.ac_source {
+            AcSource::Battery => state_on_ac(&state),
+            AcSource::Adapter => {
+                let online = adapter_or_state_on_ac(Path::new(POWER_SUPPLY_PATH), &state);
+                adapter_debouncer.update(online, now)
+            }
+        };
+        // Check again soon whether a change holds
+        let interval = match config.debounce {
+            Some(debounce) if state_debouncer.is_pending() || adapter_debouncer.is_pending() => {
+                debounce.min(config.interval)
+            }
+            _ => config.interval,
+        };
+        let ac_transition = ac_tracker.update(ac_connected);
+        if let Some(transition) = ac_transition {
+            info!("Power supply: {:?}", transition);
+        }
+        let event = match ac_transition {
+            Some(AcTransition::Connected) => Some(Event::AcConnected),
+            Some(AcTransition::Disconnected) => Some(Event::AcDisconnected),
+            None => None,
+        };
+        if let Some(event) = event {
+            match_events(&mut events, event, &charge_value, &clock, &format_obj)?;
+        }
+        if state == State::Full && last_state.is_some_and(|last| last != State::Full) {
+            match_events(&mut events, Event::Full, &charge_value, &clock, &format_obj)?;
+        }
+        last_state = Some(state);
+        match_above(&mut events, &charge_value, &clock, &format_obj)?;
+        let mut next_poll = None;
+        if ac_connected {
+            // If connected to a power supply there is nothing else to do
+            actions.iter_mut().for_each(Countdown::cancel_countdown);
+            rearm_actions(&mut actions, &charge_value, &mut last_action_index);
+            last_action_index = usize::MAX; // Reset state
+            charge_history.clear();
+        } else {
+            match_actions(
+                &mut actions,
+                &charge_value,
+                &mut last_action_index,
+                &clock,
+                &format_obj,
+            )
+            .with_context(|| "Failed")?;
+            if state == State::Discharging {
+                match_power_draw(&mut power_draw, &energy_rate, &clock, &format_obj)?;
+            }
+            if !drop_actions.is_empty() {
+                charge_history.record(now, charge_value);
+                match_drop(&mut drop_actions, &charge_history, &clock, &format_obj)?;
+            }
+            // Poll faster while a countdown is running
+            next_poll = tick_countdowns(&mut actions, now, &format_obj)?;
+        }
+        // Pick up results of commands, polling faster while one has to be stopped
+        let now = clock.now();
+        let command_actions = all_actions(
             &mut actions,
-            &charge_value,
-            &mut last_action_index,
-            &format_obj,
-        )
-        .with_context(|| "Failed")?;
-        thread::sleep(config.interval);
+            &mut events,
+            &mut power_draw,
+            &mut drop_actions,
+            &mut temperature_actions,
+            &mut health_actions,
+        );
+        let command_poll = poll_commands(command_actions, now, &format_obj)?;
+        next_poll = next_poll.into_iter().chain(command_poll).min();
+        thread::sleep(next_poll.map_or(interval, |next_poll| next_poll.min(interval)));
+    }
+}
+
+fn resolve_thresholds(triggers: &mut [Trigger], battery: &Battery) {
+    let energy_full = battery.energy_full().get::<watt_hour>();
+    let energy_full_design = battery.energy_full_design().get::<watt_hour>();
+    for trigger in triggers.iter_mut() {
+        trigger.resolve_threshold(energy_full, energy_full_design);
+    }
+}
+
+// Sort actions by threshold, keeping track of the last action taken if the order changes
+fn sort_by_threshold(actions: &mut Vec<Trigger>, last_action_index: &mut usize) {
+    if actions.is_sorted_by(|a, b| a.threshold() <= b.threshold()) {
+        return;
     }
+    let mut indexed: Vec<(usize, Trigger)> = mem::take(actions).into_iter().enumerate().collect();
+    indexed.sort_by(|(_, a), (_, b)| {
+        a.threshold()
+            .partial_cmp(&b.threshold())
+            .expect("Failed to sort actions by threshold")
+    });
+    *last_action_index = indexed
+        .iter()
+        .position(|(i, _)| i == last_action_index)
+        .unwrap_or(usize::MAX);
+    *actions = indexed.into_iter().map(|(_, action)| action).collect();
 }
 
 fn pick_battery(
@@ -255,63 +935,437 @@ fn pick_battery(
     }
 }
 
-fn match_actions<T: CommandRunner + DesktopNotification>(
-    actions: &mut [T],
+fn match_events<C: Clock>(
+    triggers: &mut [Trigger],
+    event: Event,
     charge_value: &f32,
-    last_action_index: &mut usize,
+    clock: &C,
     format_obj: &FormatObject,
 ) -> Result<(), anyhow::Error> {
-    for (i, action) in (actions).iter_mut().enumerate() {
-        if action.exceeds_threshold(charge_value) {
-            if i == *last_action_index {
-                break; // Action was already taken last iteration, nothing else to do
-            }
-            *last_action_index = i;
-            match trigger_action(action, format_obj) {
-                Ok(_) => (),
-                Err(e) => {
-                    // Show notification about failed action
-                    Notification::new()
-                        .summary("Battered action failed")
-                        .body(e.to_string().as_str())
-                        .urgency(Urgency::Critical)
-                        .show()
-                        .ok();
-                    return Err(e);
-                }
-            };
-            break;
-        };
+    for trigger in triggers.iter_mut().filter(|trigger| trigger.on == event) {
+        if trigger.exceeds_threshold(charge_value) {
+            fire(trigger, clock, format_obj)?;
+        }
     }
     Ok(())
 }
 
-fn trigger_action<A: CommandRunner + DesktopNotification>(
-    action: &mut A,
+fn match_above<C: Clock>(
+    triggers: &mut [Trigger],
+    charge_value: &f32,
+    clock: &C,
     format_obj: &FormatObject,
-) -> Result<()> {
-    if action.has_notify() {
-        action.show(format_obj); // Show notification
+) -> Result<(), anyhow::Error> {
+    for trigger in triggers.iter_mut().filter(|t| t.on == Event::Above) {
+        if !trigger.exceeds_threshold(charge_value) {
+            trigger.rearm(charge_value);
+            continue;
+        }
+        if trigger.fired {
+            continue; // Trigger was already taken, wait for battery level to drop again
+        }
+        trigger.fired = fire(trigger, clock, format_obj)?;
     }
-    action.run() // Run command
+    Ok(())
 }
 
-fn get_config(config_path: &PathBuf) -> Result<Config, anyhow::Error> {
-    let config_values = match std::fs::read_to_string(config_path) {
-        Ok(config_values) => config_values,
-        Err(e) => {
-            if e.kind() == std::io::ErrorKind::NotFound {
-                warn!(
-                    "Config file not found at '{}'; falling back to defaults",
-                    config_path.display()
-                );
-                String::new()
-            } else {
-                return Err(anyhow::Error::from(e));
-            }
-        }
-    };
-    let config: Config = toml::from_str(&config_values)
+fn rearm_actions<T: Hysteresis>(
+    actions: &mut [T],
+    charge_value: &f32,
+    last_action_index: &mut usize,
+) {
+    for (i, action) in actions.iter_mut().enumerate() {
+        if action.rearm(charge_value) && i == *last_action_index {
+            *last_action_index = usize::MAX; // Re-armed action may be taken again
+        }
+    }
+}
+
+fn match_actions<T, C>(
+    actions: &mut [T],
+    charge_value: &f32,
+    last_action_index: &mut usize,
+    clock: &C,
+    format_obj: &FormatObject,
+) -> Result<(), anyhow::Error>
+where
+    T: CommandRunner
+        + DesktopNotification
+        + Hysteresis
+        + Repeat
+        + Countdown
+        + Schedule
+        + Condition
+        + Exclusive
+        + Toggle,
+    C: Clock,
+{
+    let now = clock.now();
+    let local_now = clock.local_now();
+    rearm_actions(actions, charge_value, last_action_index);
+    // Skip disabled actions and actions outside of their schedule or conditions
+    let applies = |action: &T| {
+        action.is_enabled() && action.is_scheduled(&local_now) && action.condition_met(format_obj)
+    };
+    let Some(i) = actions
+        .iter()
+        .position(|action| applies(action) && action.exceeds_threshold(charge_value))
+    else {
+        return Ok(());
+    };
+    let action = &mut actions[i];
+    if i == *last_action_index
+        && action.repeat_due(now)
+        && action.countdown_remaining(now).is_none()
+    {
+        action.mark_repeated(now);
+        if let Err(e) = repeat_action(action, format_obj) {
+            handle_failure(action, now, e)?;
+        }
+        return Ok(());
+    }
+    if i == *last_action_index || !action.is_armed() {
+        return Ok(()); // Action was already taken, nothing else to do
+    }
+    // Non-exclusive actions which were skipped over run first, in the order they were crossed
+    let skipped = (i + 1)..(*last_action_index).min(actions.len());
+    *last_action_index = i;
+    for j in skipped.rev() {
+        let action = &mut actions[j];
+        if !action.is_exclusive() && action.is_armed() && applies(action) {
+            take_action(action, now, format_obj)?;
+        }
+    }
+    take_action(&mut actions[i], now, format_obj)
+}
+
+fn take_action<T>(action: &mut T, now: Instant, format_obj: &FormatObject) -> Result<()>
+where
+    T: CommandRunner + DesktopNotification + Hysteresis + Repeat + Countdown,
+{
+    action.disarm();
+    action.mark_taken(now);
+    if action.start_countdown(now) {
+        return Ok(()); // Countdown takes care of notification and command
+    }
+    if let Err(e) = trigger_action(action, format_obj) {
+        handle_failure(action, now, e)?;
+    }
+    Ok(())
+}
+
+fn tick_countdowns<T: CommandRunner + DesktopNotification + Countdown>(
+    actions: &mut [T],
+    now: Instant,
+    format_obj: &FormatObject,
+) -> Result<Option<Duration>, anyhow::Error> {
+    let mut next_tick: Option<Duration> = None;
+    for action in actions.iter_mut() {
+        let Some(remaining) = action.countdown_remaining(now) else {
+            continue;
+        };
+        if remaining.is_zero() {
+            action.finish_countdown();
+            if let Err(e) = action.run(format_obj) {
+                handle_failure(action, now, e)?;
+            }
+            continue;
+        }
+        if action.has_notify() {
+            let seconds = remaining.as_secs_f32().ceil() as u64;
+            let format_obj = FormatObject {
+                countdown: &seconds,
+                ..*format_obj
+            };
+            action.show(&format_obj); // Show or update countdown notification
+        }
+        let interval = action.countdown_interval().min(remaining);
+        next_tick = Some(next_tick.map_or(interval, |tick| tick.min(interval)));
+    }
+    Ok(next_tick)
+}
+
+fn match_power_draw<C: Clock>(
+    actions: &mut [PowerDrawAction],
+    energy_rate: &f32,
+    clock: &C,
+    format_obj: &FormatObject,
+) -> Result<(), anyhow::Error> {
+    let now = clock.now();
+    for action in actions.iter_mut() {
+        if !action.exceeds_threshold(energy_rate) {
+            action.reset();
+            continue;
+        }
+        let above_since = *action.above_since.get_or_insert(now);
+        if action.fired || now.duration_since(above_since) < action.duration {
+            continue; // Power draw has not been sustained long enough, or action already ran
+        }
+        action.fired = fire(action, clock, format_obj)?;
+    }
+    Ok(())
+}
+
+fn match_drop<C: Clock>(
+    actions: &mut [DropAction],
+    history: &ChargeHistory,
+    clock: &C,
+    format_obj: &FormatObject,
+) -> Result<(), anyhow::Error> {
+    let now = clock.now();
+    for action in actions.iter_mut() {
+        let Some(observed) = history.drop_within(action.within, now) else {
+            continue;
+        };
+        if !action.exceeds_threshold(&observed.drop) {
+            action.fired = false; // Battery level is steady again
+            continue;
+        }
+        if action.fired {
+            continue; // Action was already taken for this drop
+        }
+        // Expressed as percentage points, like $percentage
+        let drop = percent(observed.drop);
+        let drop_rate = percent(observed.per_minute);
+        info!("Drop:   {} points ({} per minute)", drop, drop_rate);
+        let format_obj = FormatObject {
+            drop: &drop,
+            drop_rate: &drop_rate,
+            ..*format_obj
+        };
+        action.fired = fire(action, clock, &format_obj)?;
+    }
+    Ok(())
+}
+
+fn match_temperature<C: Clock>(
+    actions: &mut [TemperatureAction],
+    temperature: &f32,
+    clock: &C,
+    format_obj: &FormatObject,
+) -> Result<(), anyhow::Error> {
+    for action in actions.iter_mut() {
+        if !action.exceeds_threshold(temperature) {
+            action.rearm(temperature);
+            continue;
+        }
+        if action.fired {
+            continue; // Action was already taken, wait for temperature to drop again
+        }
+        action.fired = fire(action, clock, format_obj)?;
+    }
+    Ok(())
+}
+
+fn match_health<C: Clock>(
+    actions: &mut [HealthAction],
+    health: &f32,
+    cycle_count: Option<u32>,
+    clock: &C,
+    format_obj: &FormatObject,
+) -> Result<(), anyhow::Error> {
+    for action in actions.iter_mut() {
+        let cycles_exceeded = cycle_count.is_some_and(|c| action.exceeds_cycle_count(&c));
+        if action.exceeds_threshold(health) || cycles_exceeded {
+            fire(action, clock, format_obj)?;
+        }
+    }
+    Ok(())
+}
+
+// Actions of all kinds, for what they have in common
+fn all_actions<'a>(
+    actions: &'a mut [Trigger],
+    events: &'a mut [Trigger],
+    power_draw: &'a mut [PowerDrawAction],
+    drop_actions: &'a mut [DropAction],
+    temperature_actions: &'a mut [TemperatureAction],
+    health_actions: &'a mut [HealthAction],
+) -> impl Iterator<Item = &'a mut dyn DesktopNotification> {
+    actions
+        .iter_mut()
+        .chain(events.iter_mut())
+        .map(|a| a as &mut dyn DesktopNotification)
+        .chain(power_draw.iter_mut().map(|a| a as _))
+        .chain(drop_actions.iter_mut().map(|a| a as _))
+        .chain(temperature_actions.iter_mut().map(|a| a as _))
+        .chain(health_actions.iter_mut().map(|a| a as _))
+}
+
+// Report commands which finished or timed out in the background
+fn poll_commands<'a, A: DesktopNotification + ?Sized + 'a>(
+    actions: impl IntoIterator<Item = &'a mut A>,
+    now: Instant,
+    format_obj: &FormatObject,
+) -> Result<Option<Duration>> {
+    let mut next_poll: Option<Duration> = None;
+    for action in actions {
+        let state = action.command_state();
+        if !state.is_running() && state.retry_at.is_some_and(|retry_at| now >= retry_at) {
+            state.retry_at = None;
+            if let Err(e) = action.spawn(format_obj) {
+                handle_failure(action, now, e)?;
+            }
+        }
+        match action.poll(now) {
+            Ok(poll) => next_poll = next_poll.into_iter().chain(poll).min(),
+            Err(e) => handle_failure(action, now, e)?,
+        }
+        let state = action.command_state();
+        if state.notify_pending && state.is_running() {
+            next_poll = next_poll.into_iter().chain([COMMAND_POLL_INTERVAL]).min();
+        } else if state.notify_pending && state.retry_at.is_none() {
+            state.notify_pending = false;
+            action.show(format_obj); // Now that the output is known
+        }
+        let retry_in = action
+            .command_state()
+            .retry_at
+            .map(|retry_at| retry_at.saturating_duration_since(now));
+        next_poll = next_poll.into_iter().chain(retry_in).min();
+    }
+    Ok(next_poll)
+}
+
+// Report a failed command and apply the failure policy of the action
+fn handle_failure<A: CommandRunner + ?Sized>(
+    action: &mut A,
+    now: Instant,
+    e: anyhow::Error,
+) -> Result<()> {
+    let on_failure = action.action().on_failure.unwrap_or_default();
+    // Retrying with `on_failure = "retry"` doesn't need `retries`
+    let retries = match (action.action().retries, on_failure) {
+        (Some(retries), _) => retries,
+        (None, OnFailure::Retry) => 1,
+        (None, _) => 0,
+    };
+    let retry_delay = action.action().retry_delay.unwrap_or(DEFAULT_RETRY_DELAY);
+    let state = action.command_state();
+    if state.retries < retries {
+        // Exponential backoff, only the final failure gets reported
+        let delay = retry_delay.saturating_mul(2u32.saturating_pow(state.retries));
+        state.retries += 1;
+        state.retry_at = Some(now + delay);
+        warn!(
+            "{:#}; retrying in {}s ({}/{})",
+            e,
+            delay.as_secs(),
+            state.retries,
+            retries
+        );
+        return Ok(());
+    }
+    let e = report_failure(action, e);
+    if on_failure == OnFailure::Exit {
+        return Err(e);
+    }
+    error!("{:#}", e);
+    if on_failure == OnFailure::DisableAction {
+        warn!("Disabling action until battered restarts");
+        action.command_state().disabled = true;
+    }
+    Ok(())
+}
+
+// Show notification about failed action, naming it if possible
+fn report_failure<A: CommandRunner + ?Sized>(action: &mut A, e: anyhow::Error) -> anyhow::Error {
+    let (summary, e) = match action.name() {
+        Some(name) => (
+            format!("Battered action '{}' failed", name),
+            e.context(format!("Action '{}' failed", name)),
+        ),
+        None => (String::from("Battered action failed"), e),
+    };
+    let mut body = format!("{:#}", e);
+    if action.action().notify_output {
+        let output = action.command_state().output.as_ref().map(Output::combined);
+        if let Some(output) = output.filter(|output| !output.is_empty()) {
+            body = format!("{}\n\n{}", body, truncate(&output, MAX_NOTIFY_OUTPUT));
+        }
+    }
+    Notification::new()
+        .summary(&summary)
+        .body(&body)
+        .urgency(Urgency::Critical)
+        .show()
+        .ok();
+    e
+}
+
+// Take an action of any kind, unless it is disabled, outside of its schedule or its condition
+// isn't met. Returns whether it was taken
+fn fire<A: DesktopNotification, C: Clock>(
+    action: &mut A,
+    clock: &C,
+    format_obj: &FormatObject,
+) -> Result<bool> {
+    if !action.is_enabled()
+        || !action.is_scheduled(&clock.local_now())
+        || !action.condition_met(format_obj)
+    {
+        return Ok(false);
+    }
+    if let Err(e) = trigger_action(action, format_obj) {
+        handle_failure(action, clock.now(), e)?;
+    }
+    Ok(true)
+}
+
+fn trigger_action<A: CommandRunner + DesktopNotification>(
+    action: &mut A,
+    format_obj: &FormatObject,
+) -> Result<()> {
+    if let Some(name) = action.name() {
+        info!("Taking action '{}'", name);
+    }
+    notify(action, format_obj, true);
+    action.run(format_obj) // Run command
+}
+
+// Show notification, unless it has to wait for the output of the command about to run
+fn notify<A: CommandRunner + DesktopNotification>(
+    action: &mut A,
+    format_obj: &FormatObject,
+    runs_command: bool,
+) {
+    if !action.has_notify() {
+        return;
+    }
+    if runs_command && !action.all_commands().is_empty() && action.shows_command_output() {
+        action.command_state().notify_pending = true; // Shown by `poll_commands`
+    } else {
+        action.show(format_obj);
+    }
+}
+
+fn repeat_action<A: CommandRunner + DesktopNotification + Repeat>(
+    action: &mut A,
+    format_obj: &FormatObject,
+) -> Result<()> {
+    notify(action, format_obj, action.repeats_command()); // Show notification again
+    if action.repeats_command() {
+        return action.run(format_obj); // Run command again
+    }
+    Ok(())
+}
+
+fn get_config(config_path: &PathBuf) -> Result<Config, anyhow::Error> {
+    let config_values = match std::fs::read_to_string(config_path) {
+        Ok(config_values) => config_values,
+        Err(e) => {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                warn!(
+                    "Config file not found at '{}'; falling back to defaults",
+                    config_path.display()
+                );
+                String::new()
+            } else {
+                return Err(anyhow::Error::from(e));
+            }
+        }
+    };
+    let config: Config = parse_config(&config_values)
         .with_context(|| format!("Failed to parse config at '{}'", config_path.display()))?;
     Ok(config)
 }
@@ -319,47 +1373,190 @@ fn get_config(config_path: &PathBuf) -> Result<Config, anyhow::Error> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use config::Notify;
+    use chrono::NaiveTime;
+    use config::{ActiveHours, SystemAction};
     use notify_rust::Timeout;
+    use std::collections::BTreeMap;
+    use std::slice;
+
+    const FORMAT_OBJ: FormatObject = FormatObject {
+        percentage: &50.0,
+        energy_rate: &0.0,
+        energy: &25.0,
+        temperature: "n/a",
+        health: &100.0,
+        energy_full: &50.0,
+        energy_full_design: &50.0,
+        cycle_count: "n/a",
+        countdown: &0,
+        drop: &0.0,
+        drop_rate: &0.0,
+        state: "discharging",
+        time_to_empty: "n/a",
+        serial: "31415",
+    };
+
+    struct FakeClock {
+        now: Instant,
+        local_now: NaiveDateTime,
+    }
+
+    impl FakeClock {
+        // Wednesday noon at the given instant
+        fn at(now: Instant) -> FakeClock {
+            FakeClock::at_local(now, "2025-06-25 12:00")
+        }
 
-    #[derive(Copy, Clone)]
-    struct MockNotify {}
+        fn at_local(now: Instant, local_now: &str) -> FakeClock {
+            FakeClock {
+                now,
+                local_now: NaiveDateTime::parse_from_str(local_now, "%Y-%m-%d %H:%M").unwrap(),
+            }
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> Instant {
+            self.now
+        }
+        fn local_now(&self) -> NaiveDateTime {
+            self.local_now
+        }
+    }
+
+    // Wait for background commands to finish, reporting failures like the main loop
+    // Instead of waiting for retries, time jumps ahead to the next one
+    fn wait_for_commands<A: CommandRunner + DesktopNotification>(actions: &mut [A]) -> Result<()> {
+        let mut now = Instant::now();
+        loop {
+            wait_for_processes(actions, now)?;
+            let Some(retry_at) = actions
+                .iter_mut()
+                .filter_map(|action| action.command_state().retry_at)
+                .min()
+            else {
+                return Ok(());
+            };
+            now = retry_at;
+            poll_commands(&mut *actions, now, &FORMAT_OBJ)?;
+        }
+    }
+
+    // Failures are noticed at `now`, however long the processes take
+    fn wait_for_processes<A: CommandRunner + DesktopNotification>(
+        actions: &mut [A],
+        now: Instant,
+    ) -> Result<()> {
+        loop {
+            poll_commands(&mut *actions, now, &FORMAT_OBJ)?;
+            if !actions
+                .iter_mut()
+                .any(|action| action.command_state().is_running())
+            {
+                return Ok(());
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
 
-    #[derive(Copy, Clone)]
+    // Counts notifications and commands of a trigger instead of showing and running them
+    #[derive(Default)]
     struct MockAction {
         show_call_count: usize,
         run_call_count: usize,
-        notify: Option<MockNotify>,
-        percentage: f32,
+        trigger: Trigger,
+    }
+
+    impl From<Trigger> for MockAction {
+        fn from(trigger: Trigger) -> Self {
+            MockAction {
+                trigger,
+                ..Default::default()
+            }
+        }
     }
 
     impl DesktopNotification for MockAction {
         fn show(&mut self, _format_obj: &FormatObject) {
             self.show_call_count += 1;
         }
-        fn has_notify(&self) -> bool {
-            self.notify.is_some()
+    }
+
+    impl Hysteresis for MockAction {
+        fn is_armed(&self) -> bool {
+            self.trigger.is_armed()
+        }
+        fn disarm(&mut self) {
+            self.trigger.disarm()
+        }
+        fn rearm(&mut self, value: &f32) -> bool {
+            self.trigger.rearm(value)
+        }
+    }
+
+    impl Repeat for MockAction {
+        fn repeat_due(&self, now: Instant) -> bool {
+            self.trigger.repeat_due(now)
+        }
+        fn repeats_command(&self) -> bool {
+            self.trigger.repeats_command()
+        }
+        fn mark_taken(&mut self, now: Instant) {
+            self.trigger.mark_taken(now)
+        }
+        fn mark_repeated(&mut self, now: Instant) {
+            self.trigger.mark_repeated(now)
+        }
+    }
+
+    impl Countdown for MockAction {
+        fn start_countdown(&mut self, now: Instant) -> bool {
+            self.trigger.start_countdown(now)
+        }
+        fn countdown_remaining(&self, now: Instant) -> Option<Duration> {
+            self.trigger.countdown_remaining(now)
+        }
+        fn countdown_interval(&self) -> Duration {
+            self.trigger.countdown_interval()
+        }
+        fn finish_countdown(&mut self) {
+            self.trigger.finish_countdown()
+        }
+        fn cancel_countdown(&mut self) {
+            self.trigger.cancel_countdown()
         }
-        fn fill_template<T: Template>(&self, _input_string: String, _format_obj: &T) -> String {
-            String::from("")
+    }
+
+    impl Exclusive for MockAction {
+        fn is_exclusive(&self) -> bool {
+            self.trigger.is_exclusive()
         }
     }
 
     impl CommandRunner for MockAction {
-        fn run(&mut self) -> Result<()> {
+        fn action(&self) -> &Action {
+            &self.trigger.action
+        }
+        fn action_mut(&mut self) -> &mut Action {
+            &mut self.trigger.action
+        }
+        fn run(&mut self, _format_obj: &FormatObject) -> Result<()> {
             self.run_call_count += 1;
             Ok(())
         }
         fn exceeds_threshold(&self, value: &f32) -> bool {
-            value < &self.percentage
+            self.trigger.exceeds_threshold(value)
+        }
+        fn trigger(&self) -> String {
+            CommandRunner::trigger(&self.trigger)
+        }
+        fn threshold_value(&self) -> Option<f32> {
+            self.trigger.threshold_value()
         }
     }
 
-    #[test]
-    fn test_has_notify() {
-        let action_w_notify = Action {
-            percentage: 0.5,
-            command: None,
+    fn notify_action() -> Action {
+        Action {
             notify: Some(Notify {
                 summary: String::from(""),
                 body: None,
@@ -367,31 +1564,54 @@ mod tests {
                 icon: String::from(""),
                 timeout: Timeout::Default,
             }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_has_notify() {
+        let action_w_notify = Trigger {
+            percentage: Some(0.5),
+            action: Action {
+                notify: Some(Notify {
+                    summary: String::from(""),
+                    body: None,
+                    urgency: Urgency::Low,
+                    icon: String::from(""),
+                    timeout: Timeout::Default,
+                }),
+                ..Default::default()
+            },
+            ..Default::default()
         };
         let has_notify = action_w_notify.has_notify();
-        assert_eq!(has_notify, true);
+        assert!(has_notify);
     }
 
     #[test]
     fn test_has_no_notify() {
-        let action_w_notify = Action {
-            percentage: 0.5,
-            command: None,
-            notify: None,
+        let action_w_notify = Trigger {
+            percentage: Some(0.5),
+            action: Action {
+                notify: None,
+                ..Default::default()
+            },
+            ..Default::default()
         };
         let has_notify = action_w_notify.has_notify();
-        assert_eq!(has_notify, false);
+        assert!(!has_notify);
     }
 
     #[test]
     fn test_threshold_without_notification() {
-        let mut action = MockAction {
-            show_call_count: 0,
-            run_call_count: 0,
-            percentage: 0.5,
-            notify: None,
+        let mut action = MockAction::from(Trigger {
+            percentage: Some(0.5),
+            ..Default::default()
+        });
+        let format_obj = FormatObject {
+            percentage: &50.0,
+            ..FORMAT_OBJ
         };
-        let format_obj = FormatObject { percentage: &50.0 };
         let result = trigger_action(&mut action, &format_obj);
         assert!(result.is_ok());
         assert_eq!(action.show_call_count, 0);
@@ -400,15 +1620,16 @@ mod tests {
 
     #[test]
     fn test_threshold_with_notification() {
-        let mock_notify = MockNotify {};
-        let mut action = MockAction {
-            run_call_count: 0,
-            show_call_count: 0,
-            percentage: 0.5,
-            notify: Some(mock_notify),
-        };
+        let mut action = MockAction::from(Trigger {
+            percentage: Some(0.5),
+            action: notify_action(),
+            ..Default::default()
+        });
 
-        let format_obj = FormatObject { percentage: &50.0 };
+        let format_obj = FormatObject {
+            percentage: &50.0,
+            ..FORMAT_OBJ
+        };
         let result = trigger_action(&mut action, &format_obj);
         assert!(result.is_ok());
         assert_eq!(action.show_call_count, 1);
@@ -417,159 +1638,1329 @@ mod tests {
 
     #[test]
     fn test_threshold_below_threshold_fn() {
-        let action = Action {
-            percentage: 0.5,
-            command: None,
-            notify: None,
+        let action = Trigger {
+            percentage: Some(0.5),
+            ..Default::default()
         };
         let charge_value_below = 0.3; // Value below percentage threshold
         let charge_value_above = 0.8; // Value below percentage threshold
 
         let below_result = action.exceeds_threshold(&charge_value_below);
-        assert_eq!(below_result, true);
+        assert!(below_result);
 
         let above_result = action.exceeds_threshold(&charge_value_above);
-        assert_eq!(above_result, false);
+        assert!(!above_result);
     }
 
     #[test]
-    fn test_threshold_action_above_threshold() {
-        let mock_notify = MockNotify {};
-        let action = MockAction {
-            run_call_count: 0,
-            show_call_count: 0,
-            percentage: 0.5,
-            notify: Some(mock_notify),
+    fn test_energy_threshold() {
+        let mut action = Trigger {
+            energy_below: Some(8.0),
+            ..Default::default()
         };
+        // Energy threshold is unknown until resolved against the battery
+        assert!(!action.exceeds_threshold(&0.1));
+
+        action.resolve_threshold(40.0, 57.0);
+        assert!(action.exceeds_threshold(&0.19)); // 7.6 Wh left
+        assert!(!action.exceeds_threshold(&0.21)); // 8.4 Wh left
+    }
+
+    #[test]
+    fn test_resort_on_threshold_change() {
+        let mut actions = vec![
+            Trigger {
+                energy_below: Some(8.0),
+                ..Default::default()
+            },
+            Trigger {
+                percentage: Some(0.25),
+                ..Default::default()
+            },
+        ];
+        let mut last_action_index = usize::MAX;
+        resolve_thresholds_to(&mut actions, 40.0);
+        sort_by_threshold(&mut actions, &mut last_action_index);
+        assert_eq!(actions[0].threshold(), Some(0.2));
+        last_action_index = 0;
+
+        // Worn battery moves the energy threshold above the percentage threshold
+        resolve_thresholds_to(&mut actions, 20.0);
+        sort_by_threshold(&mut actions, &mut last_action_index);
+        assert_eq!(actions[0].threshold(), Some(0.25));
+        assert_eq!(actions[1].threshold(), Some(0.4));
+        assert_eq!(last_action_index, 1); // Still points to the energy action
+    }
+
+    fn resolve_thresholds_to(actions: &mut [Trigger], energy_full: f32) {
+        for action in actions.iter_mut() {
+            action.resolve_threshold(energy_full, 57.0);
+        }
+    }
+
+    #[test]
+    fn test_threshold_action_above_threshold() {
+        let clock = FakeClock::at(Instant::now());
+        let action = MockAction::from(Trigger {
+            percentage: Some(0.5),
+            action: notify_action(),
+            ..Default::default()
+        });
         let charge_value = 0.7; // Value above percentage threshold
 
         let mut actions = vec![action];
         let mut last_action_index: usize = 0;
-        let format_obj = FormatObject { percentage: &70.0 };
+        let format_obj = FormatObject {
+            percentage: &70.0,
+            ..FORMAT_OBJ
+        };
         let result = match_actions(
             &mut actions,
             &charge_value,
             &mut last_action_index,
+            &clock,
             &format_obj,
         );
         assert!(result.is_ok());
-        assert_eq!(action.show_call_count, 0);
-        assert_eq!(action.run_call_count, 0);
+        assert_eq!(actions[0].show_call_count, 0);
+        assert_eq!(actions[0].run_call_count, 0);
     }
 
     #[test]
     fn test_threshold_action_below_threshold() {
-        let action = MockAction {
-            run_call_count: 0,
-            show_call_count: 0,
-            percentage: 0.5,
-            notify: None,
-        };
+        let clock = FakeClock::at(Instant::now());
+        let action = MockAction::from(Trigger {
+            percentage: Some(0.5),
+            ..Default::default()
+        });
         let charge_value = 0.3; // Value below percentage threshold
 
-        let mut actions = vec![action]; // Creates a copy
+        let mut actions = vec![action];
         let mut last_action_index = usize::MAX;
-        let format_obj = FormatObject { percentage: &30.0 };
+        let format_obj = FormatObject {
+            percentage: &30.0,
+            ..FORMAT_OBJ
+        };
         let result = match_actions(
             &mut actions,
             &charge_value,
             &mut last_action_index,
+            &clock,
             &format_obj,
         );
 
-        let result_action = actions[0];
+        let result_action = &actions[0];
         assert!(result.is_ok());
         assert_eq!(result_action.run_call_count, 1);
     }
 
-    #[test]
-    fn test_successful_action() {
-        let mut action = Action {
-            percentage: 0.5,
-            notify: None,
-            command: Some(vec![String::from("true")]),
-        };
-        let format_obj = FormatObject { percentage: &50.0 };
-        let result = trigger_action(&mut action, &format_obj);
-        assert!(result.is_ok());
+    fn oscillate(actions: &mut [MockAction], charge_values: &[f32], last_action_index: &mut usize) {
+        for charge_value in charge_values {
+            match_actions(
+                actions,
+                charge_value,
+                last_action_index,
+                &FakeClock::at(Instant::now()),
+                &FORMAT_OBJ,
+            )
+            .unwrap();
+        }
     }
 
     #[test]
-    fn test_no_action() {
-        let mut action = Action {
-            percentage: 0.5,
-            notify: None,
-            command: None,
-        };
-        let format_obj = FormatObject { percentage: &50.0 };
-        let result = trigger_action(&mut action, &format_obj);
-        assert!(result.is_ok());
+    fn test_oscillation_without_hysteresis() {
+        let mut actions = vec![
+            MockAction::from(Trigger {
+                percentage: Some(0.2),
+                ..Default::default()
+            }),
+            MockAction::from(Trigger {
+                percentage: Some(0.5),
+                ..Default::default()
+            }),
+        ];
+        let mut last_action_index = usize::MAX;
+        oscillate(
+            &mut actions,
+            &[0.6, 0.49, 0.199, 0.201, 0.199, 0.201],
+            &mut last_action_index,
+        );
+        // Without hysteresis neighbouring actions take turns
+        assert_eq!(actions[0].run_call_count, 2);
+        assert_eq!(actions[1].run_call_count, 3);
     }
 
     #[test]
-    fn test_failing_action() {
-        let mut action = Action {
-            percentage: 0.5,
-            notify: None,
-            command: Some(vec![String::from("false")]),
-        };
-        let format_obj = FormatObject { percentage: &50.0 };
-        let result = trigger_action(&mut action, &format_obj);
-        assert!(result.is_err());
+    fn test_oscillation_with_hysteresis() {
+        let mut actions = vec![
+            MockAction::from(Trigger {
+                percentage: Some(0.2),
+                hysteresis: Some(0.02),
+                ..Default::default()
+            }),
+            MockAction::from(Trigger {
+                percentage: Some(0.5),
+                hysteresis: Some(0.02),
+                ..Default::default()
+            }),
+        ];
+        let mut last_action_index = usize::MAX;
+        oscillate(
+            &mut actions,
+            &[0.6, 0.49, 0.199, 0.201, 0.199, 0.201, 0.219, 0.199],
+            &mut last_action_index,
+        );
+        assert_eq!(actions[0].run_call_count, 1);
+        assert_eq!(actions[1].run_call_count, 1);
+
+        // Charge rising above threshold plus hysteresis re-arms the action
+        oscillate(&mut actions, &[0.22, 0.199], &mut last_action_index);
+        assert_eq!(actions[0].run_call_count, 2);
+        assert_eq!(actions[1].run_call_count, 1);
     }
 
     #[test]
-    fn test_successful_on_ac_action() {
-        let mut action = OnAcAction {
-            percentage: 0.0,
-            notify: None,
-            command: Some(vec![String::from("true")]),
-        };
-        let format_obj = FormatObject { percentage: &50.0 };
-        let result = trigger_action(&mut action, &format_obj);
-        assert!(result.is_ok());
+    fn test_oscillation_around_upper_threshold_with_hysteresis() {
+        let mut actions = vec![MockAction::from(Trigger {
+            percentage: Some(0.5),
+            hysteresis: Some(0.05),
+            ..Default::default()
+        })];
+        let mut last_action_index = usize::MAX;
+        oscillate(
+            &mut actions,
+            &[0.499, 0.501, 0.499, 0.53, 0.499],
+            &mut last_action_index,
+        );
+        assert_eq!(actions[0].run_call_count, 1);
+
+        oscillate(&mut actions, &[0.55, 0.499], &mut last_action_index);
+        assert_eq!(actions[0].run_call_count, 2);
     }
 
     #[test]
-    fn test_no_on_ac_action() {
-        let mut action = OnAcAction {
-            percentage: 0.1,
-            notify: None,
-            command: None,
-        };
-        let format_obj = FormatObject { percentage: &50.0 };
+    fn test_rearm_on_charging() {
+        let mut actions = vec![MockAction::from(Trigger {
+            percentage: Some(0.2),
+            hysteresis: Some(0.02),
+            ..Default::default()
+        })];
+        let mut last_action_index = usize::MAX;
+        oscillate(&mut actions, &[0.19], &mut last_action_index);
+        assert!(actions[0].trigger.fired);
+
+        // Charging without passing threshold plus hysteresis keeps action disarmed
+        rearm_actions(&mut actions, &0.21, &mut last_action_index);
+        assert!(actions[0].trigger.fired);
+        rearm_actions(&mut actions, &0.25, &mut last_action_index);
+        assert!(!actions[0].trigger.fired);
+        assert_eq!(last_action_index, usize::MAX);
+    }
+
+    #[test]
+    fn test_repeat_notification() {
+        let mut actions = vec![MockAction::from(Trigger {
+            percentage: Some(0.1),
+            action: notify_action(),
+            repeat: Some(Duration::from_secs(60)),
+            ..Default::default()
+        })];
+        let mut last_action_index = usize::MAX;
+        let start = Instant::now();
+        for seconds in [0, 30, 59, 60, 90, 120, 150] {
+            match_actions(
+                &mut actions,
+                &0.05,
+                &mut last_action_index,
+                &FakeClock::at(start + Duration::from_secs(seconds)),
+                &FORMAT_OBJ,
+            )
+            .unwrap();
+        }
+        // Notification is shown again every 60 seconds, command only runs once
+        assert_eq!(actions[0].show_call_count, 3);
+        assert_eq!(actions[0].run_call_count, 1);
+    }
+
+    #[test]
+    fn test_repeat_command_with_limit() {
+        let mut actions = vec![MockAction::from(Trigger {
+            percentage: Some(0.1),
+            action: notify_action(),
+            repeat: Some(Duration::from_secs(60)),
+            repeat_limit: Some(2),
+            repeat_command: true,
+            ..Default::default()
+        })];
+        let mut last_action_index = usize::MAX;
+        let start = Instant::now();
+        for minutes in 0..10 {
+            match_actions(
+                &mut actions,
+                &0.05,
+                &mut last_action_index,
+                &FakeClock::at(start + Duration::from_secs(minutes * 60)),
+                &FORMAT_OBJ,
+            )
+            .unwrap();
+        }
+        assert_eq!(actions[0].show_call_count, 3);
+        assert_eq!(actions[0].run_call_count, 3);
+    }
+
+    #[test]
+    fn test_no_repeat_once_inactive() {
+        let mut actions = vec![
+            MockAction::from(Trigger {
+                percentage: Some(0.1),
+                ..Default::default()
+            }),
+            MockAction::from(Trigger {
+                percentage: Some(0.2),
+                repeat: Some(Duration::from_secs(60)),
+                repeat_command: true,
+                ..Default::default()
+            }),
+        ];
+        let mut last_action_index = usize::MAX;
+        let start = Instant::now();
+        match_actions(
+            &mut actions,
+            &0.15,
+            &mut last_action_index,
+            &FakeClock::at(start),
+            &FORMAT_OBJ,
+        )
+        .unwrap();
+        // Lower action takes over, so the upper one is no longer repeated
+        match_actions(
+            &mut actions,
+            &0.05,
+            &mut last_action_index,
+            &FakeClock::at(start + Duration::from_secs(60)),
+            &FORMAT_OBJ,
+        )
+        .unwrap();
+        match_actions(
+            &mut actions,
+            &0.05,
+            &mut last_action_index,
+            &FakeClock::at(start + Duration::from_secs(120)),
+            &FORMAT_OBJ,
+        )
+        .unwrap();
+        assert_eq!(actions[0].run_call_count, 1);
+        assert_eq!(actions[1].run_call_count, 1);
+    }
+
+    #[test]
+    fn test_exclusive_actions_are_skipped_over() {
+        let mut actions = vec![
+            MockAction::from(Trigger {
+                percentage: Some(0.05),
+                ..Default::default()
+            }),
+            MockAction::from(Trigger {
+                percentage: Some(0.3),
+                ..Default::default()
+            }),
+            MockAction::from(Trigger {
+                percentage: Some(0.5),
+                ..Default::default()
+            }),
+        ];
+        let mut last_action_index = usize::MAX;
+        oscillate(&mut actions, &[0.6, 0.45, 0.04], &mut last_action_index);
+        assert_eq!(actions[0].run_call_count, 1);
+        assert_eq!(actions[1].run_call_count, 0);
+        assert_eq!(actions[2].run_call_count, 1);
+    }
+
+    #[test]
+    fn test_non_exclusive_actions_run_when_skipped_over() {
+        let mut actions = vec![
+            MockAction::from(Trigger {
+                percentage: Some(0.05),
+                ..Default::default()
+            }),
+            MockAction::from(Trigger {
+                percentage: Some(0.2),
+                exclusive: Some(false),
+                ..Default::default()
+            }),
+            MockAction::from(Trigger {
+                percentage: Some(0.3),
+                ..Default::default()
+            }),
+            MockAction::from(Trigger {
+                percentage: Some(0.5),
+                exclusive: Some(false),
+                ..Default::default()
+            }),
+            MockAction::from(Trigger {
+                percentage: Some(0.8),
+                exclusive: Some(false),
+                ..Default::default()
+            }),
+        ];
+        let mut last_action_index = usize::MAX;
+        oscillate(&mut actions, &[0.7, 0.04], &mut last_action_index);
+        assert_eq!(actions[0].run_call_count, 1);
+        assert_eq!(actions[1].run_call_count, 1);
+        assert_eq!(actions[2].run_call_count, 0);
+        assert_eq!(actions[3].run_call_count, 1);
+        // Action at 0.8 was already taken at 0.7 and does not run again
+        assert_eq!(actions[4].run_call_count, 1);
+        assert_eq!(last_action_index, 0);
+
+        // Nothing runs again while the battery level stays low
+        oscillate(&mut actions, &[0.04, 0.03], &mut last_action_index);
+        assert_eq!(actions.iter().map(|a| a.run_call_count).sum::<usize>(), 4);
+    }
+
+    #[test]
+    fn test_disabled_actions_are_skipped() {
+        let mut actions = vec![
+            MockAction::from(Trigger {
+                percentage: Some(0.2),
+                action: Action {
+                    enabled: Some(false),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }),
+            MockAction::from(Trigger {
+                percentage: Some(0.5),
+                ..Default::default()
+            }),
+        ];
+        let mut last_action_index = usize::MAX;
+        oscillate(&mut actions, &[0.15], &mut last_action_index);
+        assert_eq!(actions[0].run_call_count, 0);
+        assert_eq!(actions[1].run_call_count, 1);
+
+        actions[0].trigger.action.enabled = None;
+        oscillate(&mut actions, &[0.15], &mut last_action_index);
+        assert_eq!(actions[0].run_call_count, 1);
+    }
+
+    #[test]
+    fn test_toggled_trigger() {
+        let mut trigger = Trigger {
+            action: Action {
+                name: Some(String::from("hibernate")),
+                tags: vec![String::from("powersave")],
+                enabled: Some(false),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut toggles = state::Toggles::default();
+        toggles.set("hibernate", true);
+        trigger.action.toggled = toggles.lookup(trigger.action.selectors());
+        assert!(Toggle::is_enabled(&trigger));
+
+        toggles.set("powersave", false);
+        trigger.action.toggled = toggles.lookup(trigger.action.selectors());
+        assert!(!Toggle::is_enabled(&trigger));
+    }
+
+    #[test]
+    fn test_failure_names_action() {
+        let mut action = Trigger {
+            percentage: Some(0.5),
+            action: Action {
+                name: Some(String::from("powersave")),
+                command: Some(Command::Args(vec![String::from("false")])),
+                on_failure: Some(OnFailure::Exit),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        trigger_action(&mut action, &FORMAT_OBJ).unwrap();
+        let e = wait_for_commands(slice::from_mut(&mut action)).unwrap_err();
+        assert!(format!("{:#}", e).starts_with("Action 'powersave' failed: Command 'false' failed"));
+    }
+
+    #[test]
+    fn test_commands_run_in_background() {
+        let mut actions = vec![Trigger {
+            percentage: Some(0.5),
+            action: Action {
+                command: Some(Command::Args(vec![
+                    String::from("sleep"),
+                    String::from("30"),
+                ])),
+                timeout: Some(Duration::from_secs(10)),
+                on_failure: Some(OnFailure::Exit),
+                ..Default::default()
+            },
+            ..Default::default()
+        }];
+        let start = Instant::now();
+        trigger_action(&mut actions[0], &FORMAT_OBJ).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+        let now = Instant::now();
+        let next_poll = poll_commands(&mut actions, now, &FORMAT_OBJ).unwrap();
+        assert!(next_poll.is_some_and(|next_poll| next_poll <= Duration::from_secs(10)));
+
+        // Command is stopped once timed out, and reported as failure
+        let next_poll =
+            poll_commands(&mut actions, now + Duration::from_secs(11), &FORMAT_OBJ).unwrap();
+        assert!(next_poll.is_some());
+        let e = wait_for_commands(&mut actions).unwrap_err();
+        assert!(format!("{:#}", e).ends_with("Command 'sleep 30' timed out after 10s"));
+    }
+
+    #[test]
+    fn test_command_environment() {
+        let check = "test \"$BATTERED_PERCENTAGE\" = 50 && test \"$BATTERED_STATE\" = discharging \
+            && test \"$BATTERED_THRESHOLD\" = 25 && test \"$BATTERED_TRIGGER\" = below \
+            && test \"$BATTERED_NAME\" = powersave && test \"$BATTERED_SERIAL\" = 31415 \
+            && test \"$BATTERED_TIME_TO_EMPTY\" = n/a";
+        let mut action = Trigger {
+            percentage: Some(0.25),
+            action: Action {
+                name: Some(String::from("powersave")),
+                command: Some(Command::Args(vec![
+                    String::from("sh"),
+                    String::from("-c"),
+                    check.to_string(),
+                ])),
+                on_failure: Some(OnFailure::Exit),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        trigger_action(&mut action, &FORMAT_OBJ).unwrap();
+        assert!(wait_for_commands(slice::from_mut(&mut action)).is_ok());
+
+        let format_obj = FormatObject {
+            state: "charging",
+            ..FORMAT_OBJ
+        };
+        trigger_action(&mut action, &format_obj).unwrap();
+        assert!(wait_for_commands(slice::from_mut(&mut action)).is_err());
+    }
+
+    #[test]
+    fn test_command_environment_of_other_kinds() {
+        let check = "test \"$BATTERED_TRIGGER\" = power_draw && test \"$BATTERED_THRESHOLD\" = 35 \
+            && test -z \"${BATTERED_NAME+set}\"";
+        let mut action = PowerDrawAction {
+            watts: 35.0,
+            duration: Duration::from_secs(60),
+            action: Action {
+                command: Some(Command::Args(vec![
+                    String::from("sh"),
+                    String::from("-c"),
+                    check.to_string(),
+                ])),
+                on_failure: Some(OnFailure::Exit),
+                ..Default::default()
+            },
+            above_since: None,
+            fired: false,
+        };
+        trigger_action(&mut action, &FORMAT_OBJ).unwrap();
+        assert!(wait_for_commands(slice::from_mut(&mut action)).is_ok());
+
+        let check = "test \"$BATTERED_TRIGGER\" = health && test \"$BATTERED_THRESHOLD\" = 80";
+        let mut action = HealthAction {
+            below: Some(0.8),
+            cycle_count: None,
+            action: Action {
+                command: Some(Command::Args(vec![
+                    String::from("sh"),
+                    String::from("-c"),
+                    check.to_string(),
+                ])),
+                on_failure: Some(OnFailure::Exit),
+                ..Default::default()
+            },
+        };
+        trigger_action(&mut action, &FORMAT_OBJ).unwrap();
+        assert!(wait_for_commands(slice::from_mut(&mut action)).is_ok());
+    }
+
+    #[test]
+    fn test_placeholders_in_command() {
+        let mut action = Trigger {
+            percentage: Some(0.25),
+            // Value of a placeholder stays a single argument
+            action: Action {
+                command: Some(Command::Line(String::from(
+                    r#"sh -c 'test "$1" = "50 discharging"' sh "$percentage $state""#,
+                ))),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        trigger_action(&mut action, &FORMAT_OBJ).unwrap();
+        assert!(wait_for_commands(slice::from_mut(&mut action)).is_ok());
+    }
+
+    #[test]
+    fn test_shell_command_with_env() {
+        let mut action = Trigger {
+            percentage: Some(0.25),
+            // Placeholders are left to the shell, which only knows the environment variables
+            action: Action {
+                command: Some(Command::Line(String::from(
+                    "echo \"$PROFILE at $BATTERED_PERCENTAGE$percentage\" | tr a-z A-Z",
+                ))),
+                shell: true,
+                env: BTreeMap::from([(String::from("PROFILE"), String::from("$state"))]),
+                on_failure: Some(OnFailure::Exit),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        trigger_action(&mut action, &FORMAT_OBJ).unwrap();
+        assert!(wait_for_commands(slice::from_mut(&mut action)).is_ok());
+        assert_eq!(action.command_output(), "DISCHARGING AT 50");
+    }
+
+    #[test]
+    fn test_sequential_commands() {
+        let commands = |stop_on_failure| Trigger {
+            percentage: Some(0.25),
+            action: Action {
+                commands: ["echo dimmed", "false", "echo stopped sync"]
+                    .map(|cmd| Command::Line(String::from(cmd)))
+                    .to_vec(),
+                stop_on_failure,
+                on_failure: Some(OnFailure::Exit),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        // Later commands run despite the failure, which is reported at the end
+        let mut action = commands(false);
+        trigger_action(&mut action, &FORMAT_OBJ).unwrap();
+        let e = wait_for_commands(slice::from_mut(&mut action)).unwrap_err();
+        assert!(e.to_string().starts_with("Command 'false' failed"));
+        assert_eq!(action.command_output(), "dimmed\nstopped sync");
+
+        let mut action = commands(true);
+        trigger_action(&mut action, &FORMAT_OBJ).unwrap();
+        assert!(wait_for_commands(slice::from_mut(&mut action)).is_err());
+        assert_eq!(action.command_output(), "dimmed");
+    }
+
+    #[test]
+    fn test_parallel_commands() {
+        let mut action = Trigger {
+            percentage: Some(0.25),
+            action: Action {
+                command: Some(Command::Line(String::from("sleep 30"))),
+                commands: vec![Command::Line(String::from("false"))],
+                mode: CommandMode::Parallel,
+                stop_on_failure: true,
+                on_failure: Some(OnFailure::Exit),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let start = Instant::now();
+        trigger_action(&mut action, &FORMAT_OBJ).unwrap();
+        assert_eq!(action.action.command_state.processes.len(), 2);
+
+        // Failure stops the other command
+        let e = wait_for_commands(slice::from_mut(&mut action)).unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(
+            e.to_string(),
+            "2 commands failed: Command 'false' failed: exit status: 1; \
+            Command 'sleep 30' was stopped"
+        );
+    }
+
+    #[test]
+    fn test_system_action_skipped_after_stop_on_failure() {
+        let mut action = Trigger {
+            percentage: Some(0.25),
+            action: Action {
+                commands: vec![
+                    Command::Line(String::from("false")),
+                    Command::Line(String::from("true")),
+                ],
+                stop_on_failure: true,
+                system: Some(SystemAction::Lock),
+                on_failure: Some(OnFailure::Exit),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        trigger_action(&mut action, &FORMAT_OBJ).unwrap();
+        assert_eq!(action.action.command_state.system, Some(SystemAction::Lock));
+
+        // Only the failed command is reported, logind isn't asked
+        let e = wait_for_commands(slice::from_mut(&mut action)).unwrap_err();
+        assert_eq!(e.to_string(), "Command 'false' failed: exit status: 1");
+        assert_eq!(action.action.command_state.system, None);
+    }
+
+    #[test]
+    fn test_retries_skip_system_action() {
+        let mut actions = vec![Trigger {
+            percentage: Some(0.25),
+            action: Action {
+                command: Some(Command::Line(String::from("false"))),
+                system: Some(SystemAction::Lock),
+                on_failure: Some(OnFailure::Exit),
+                retries: Some(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        }];
+        let start = Instant::now();
+        trigger_action(&mut actions[0], &FORMAT_OBJ).unwrap();
+        assert!(wait_for_processes(&mut actions, start).is_ok());
+        assert_eq!(actions[0].action.command_state.system, None);
+
+        // Retry runs the command again, without the system action
+        let retry_at = actions[0].action.command_state.retry_at.unwrap();
+        poll_commands(&mut actions, retry_at, &FORMAT_OBJ).unwrap();
+        assert!(!actions[0].action.command_state.processes.is_empty());
+        assert_eq!(actions[0].action.command_state.system, None);
+        let e = wait_for_processes(&mut actions, retry_at).unwrap_err();
+        assert_eq!(e.to_string(), "Command 'false' failed: exit status: 1");
+    }
+
+    #[test]
+    fn test_command_output_placeholder() {
+        let mut action = Trigger {
+            percentage: Some(0.25),
+            action: Action {
+                command: Some(Command::Line(String::from(
+                    "sh -c 'echo $0 at $1' powersave $percentage",
+                ))),
+                notify: Some(Notify {
+                    summary: String::from("Battery low"),
+                    body: Some(String::from("Switched to $command_output")),
+                    urgency: Urgency::Low,
+                    icon: String::from(""),
+                    timeout: Timeout::Default,
+                }),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(action.shows_command_output());
+
+        // Notification waits for the command to finish
+        trigger_action(&mut action, &FORMAT_OBJ).unwrap();
+        assert!(action.action.command_state.notify_pending);
+        assert!(wait_for_commands(slice::from_mut(&mut action)).is_ok());
+        assert!(!action.action.command_state.notify_pending);
+        assert_eq!(action.command_output(), "powersave at 50");
+        assert_eq!(
+            action.fill_template(String::from("Switched to $command_output"), &FORMAT_OBJ),
+            "Switched to powersave at 50"
+        );
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("charging", 20), "charging");
+        assert_eq!(truncate("charging", 8), "charging");
+        assert_eq!(truncate("chärging", 3), "chä…");
+    }
+
+    #[test]
+    fn test_failure_policies() {
+        let failing_trigger = |on_failure| Trigger {
+            percentage: Some(0.5),
+            action: Action {
+                command: Some(Command::Args(vec![String::from("false")])),
+                on_failure,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        // Battered keeps running by default
+        let mut action = failing_trigger(None);
+        trigger_action(&mut action, &FORMAT_OBJ).unwrap();
+        assert!(wait_for_commands(slice::from_mut(&mut action)).is_ok());
+        assert!(action.is_enabled());
+
+        let mut action = failing_trigger(Some(OnFailure::DisableAction));
+        trigger_action(&mut action, &FORMAT_OBJ).unwrap();
+        assert!(wait_for_commands(slice::from_mut(&mut action)).is_ok());
+        assert!(!action.is_enabled());
+
+        // Command is run once more, then battered keeps running
+        let mut action = failing_trigger(Some(OnFailure::Retry));
+        trigger_action(&mut action, &FORMAT_OBJ).unwrap();
+        assert!(wait_for_commands(slice::from_mut(&mut action)).is_ok());
+        assert_eq!(action.action.command_state.retries, 1);
+        assert!(action.is_enabled());
+    }
+
+    #[test]
+    fn test_retry_succeeds() {
+        let marker = env::temp_dir().join(format!("battered-test-retry-{}", std::process::id()));
+        std::fs::remove_file(&marker).ok();
+        // Fails on the first run only
+        let script = format!("test -e {0} || {{ touch {0}; exit 1; }}", marker.display());
+        let mut action = Trigger {
+            percentage: Some(0.5),
+            action: Action {
+                command: Some(Command::Args(vec![
+                    String::from("sh"),
+                    String::from("-c"),
+                    script,
+                ])),
+                on_failure: Some(OnFailure::Retry),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        trigger_action(&mut action, &FORMAT_OBJ).unwrap();
+        let result = wait_for_commands(slice::from_mut(&mut action));
+        std::fs::remove_file(&marker).ok();
+        assert!(result.is_ok());
+        assert_eq!(action.action.command_state.retries, 1);
+
+        // Taking the action again allows for another retry
+        trigger_action(&mut action, &FORMAT_OBJ).unwrap();
+        assert_eq!(action.action.command_state.retries, 0);
+        wait_for_commands(slice::from_mut(&mut action)).unwrap();
+    }
+
+    #[test]
+    fn test_disabled_power_draw_action_is_skipped() {
+        let clock = FakeClock::at(Instant::now());
+        let mut actions = vec![PowerDrawAction {
+            watts: 30.0,
+            duration: Duration::from_secs(0),
+            action: Action {
+                command: Some(Command::Args(vec![String::from("false")])),
+                on_failure: Some(OnFailure::Exit),
+                command_state: CommandState {
+                    disabled: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            above_since: None,
+            fired: false,
+        }];
+        let result = match_power_draw(&mut actions, &35.0, &clock, &FORMAT_OBJ);
+        assert!(result.is_ok());
+        assert!(!actions[0].fired);
+        assert!(actions[0].action.command_state.processes.is_empty());
+    }
+
+    #[test]
+    fn test_retries_with_backoff() {
+        let mut actions = vec![Trigger {
+            percentage: Some(0.5),
+            action: Action {
+                command: Some(Command::Args(vec![String::from("false")])),
+                on_failure: Some(OnFailure::Exit),
+                retries: Some(2),
+                retry_delay: Some(Duration::from_secs(10)),
+                ..Default::default()
+            },
+            ..Default::default()
+        }];
+        let start = Instant::now();
+        trigger_action(&mut actions[0], &FORMAT_OBJ).unwrap();
+        assert!(wait_for_processes(&mut actions, start).is_ok());
+        let retry_at = actions[0].action.command_state.retry_at.unwrap();
+        assert_eq!(retry_at, start + Duration::from_secs(10));
+
+        // Retry is only run once due, without blocking
+        let next_poll = poll_commands(&mut actions, start + Duration::from_secs(1), &FORMAT_OBJ);
+        assert!(actions[0].action.command_state.processes.is_empty());
+        assert_eq!(next_poll.unwrap(), Some(Duration::from_secs(9)));
+        poll_commands(&mut actions, retry_at, &FORMAT_OBJ).unwrap();
+        assert!(wait_for_processes(&mut actions, retry_at).is_ok());
+
+        // Delay doubles with every retry
+        let retry_at = actions[0].action.command_state.retry_at.unwrap();
+        assert_eq!(retry_at, start + Duration::from_secs(30));
+        poll_commands(&mut actions, retry_at, &FORMAT_OBJ).unwrap();
+        assert!(wait_for_processes(&mut actions, retry_at).is_err());
+        assert_eq!(actions[0].action.command_state.retries, 2);
+        assert_eq!(actions[0].action.command_state.retry_at, None);
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = |args: &[&str]| -> Vec<String> { args.iter().map(|a| a.to_string()).collect() };
+        assert_eq!(parse_args(&args(&["-V"])).unwrap(), Cli::Version);
+        assert_eq!(parse_args(&[]).unwrap(), Cli::Run(vec![]));
+        assert_eq!(
+            parse_args(&args(&["--disable", "powersave", "--enable", "low"])).unwrap(),
+            Cli::Run(vec![
+                (String::from("powersave"), false),
+                (String::from("low"), true)
+            ])
+        );
+        assert_eq!(
+            parse_args(&args(&["disable", "powersave", "low"])).unwrap(),
+            Cli::Toggle(vec![
+                (String::from("powersave"), false),
+                (String::from("low"), false)
+            ])
+        );
+        assert!(parse_args(&args(&["enable"])).is_err());
+        assert!(parse_args(&args(&["--disable"])).is_err());
+        assert!(parse_args(&args(&["--frobnicate"])).is_err());
+    }
+
+    #[test]
+    fn test_countdown_runs_command_after_deadline() {
+        let mut actions = vec![MockAction::from(Trigger {
+            percentage: Some(0.05),
+            action: notify_action(),
+            countdown: Some(Duration::from_secs(12)),
+            countdown_interval: Duration::from_secs(5),
+            ..Default::default()
+        })];
+        let mut last_action_index = usize::MAX;
+        let start = Instant::now();
+        match_actions(
+            &mut actions,
+            &0.04,
+            &mut last_action_index,
+            &FakeClock::at(start),
+            &FORMAT_OBJ,
+        )
+        .unwrap();
+        assert_eq!(actions[0].run_call_count, 0);
+
+        let next_tick = tick_countdowns(&mut actions, start, &FORMAT_OBJ).unwrap();
+        assert_eq!(next_tick, Some(Duration::from_secs(5)));
+        let next_tick =
+            tick_countdowns(&mut actions, start + Duration::from_secs(10), &FORMAT_OBJ).unwrap();
+        assert_eq!(next_tick, Some(Duration::from_secs(2)));
+        assert_eq!(actions[0].show_call_count, 2);
+        assert_eq!(actions[0].run_call_count, 0);
+
+        let next_tick =
+            tick_countdowns(&mut actions, start + Duration::from_secs(12), &FORMAT_OBJ).unwrap();
+        assert_eq!(next_tick, None);
+        assert_eq!(actions[0].run_call_count, 1);
+    }
+
+    #[test]
+    fn test_countdown_cancelled() {
+        let mut actions = vec![MockAction::from(Trigger {
+            percentage: Some(0.05),
+            countdown: Some(Duration::from_secs(60)),
+            ..Default::default()
+        })];
+        let mut last_action_index = usize::MAX;
+        let start = Instant::now();
+        match_actions(
+            &mut actions,
+            &0.04,
+            &mut last_action_index,
+            &FakeClock::at(start),
+            &FORMAT_OBJ,
+        )
+        .unwrap();
+        tick_countdowns(&mut actions, start, &FORMAT_OBJ).unwrap();
+
+        // Connecting to AC cancels the countdown
+        actions.iter_mut().for_each(Countdown::cancel_countdown);
+        let next_tick =
+            tick_countdowns(&mut actions, start + Duration::from_secs(60), &FORMAT_OBJ).unwrap();
+        assert_eq!(next_tick, None);
+        assert_eq!(actions[0].run_call_count, 0);
+    }
+
+    #[test]
+    fn test_actions_outside_schedule_are_skipped() {
+        let day = ActiveHours {
+            start: NaiveTime::from_hms_opt(8, 0, 0).unwrap(),
+            end: NaiveTime::from_hms_opt(22, 0, 0).unwrap(),
+        };
+        let night = ActiveHours {
+            start: day.end,
+            end: day.start,
+        };
+        let mut actions = vec![
+            MockAction::from(Trigger {
+                percentage: Some(0.2),
+                action: Action {
+                    active_hours: Some(night),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }),
+            MockAction::from(Trigger {
+                percentage: Some(0.2),
+                action: Action {
+                    active_hours: Some(day),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }),
+        ];
+        let mut last_action_index = usize::MAX;
+        let start = Instant::now();
+
+        let clock = FakeClock::at_local(start, "2025-06-25 23:00");
+        match_actions(
+            &mut actions,
+            &0.15,
+            &mut last_action_index,
+            &clock,
+            &FORMAT_OBJ,
+        )
+        .unwrap();
+        assert_eq!(actions[0].run_call_count, 1);
+        assert_eq!(actions[1].run_call_count, 0);
+        assert_eq!(last_action_index, 0);
+
+        let clock = FakeClock::at_local(start, "2025-06-26 12:00");
+        match_actions(
+            &mut actions,
+            &0.15,
+            &mut last_action_index,
+            &clock,
+            &FORMAT_OBJ,
+        )
+        .unwrap();
+        assert_eq!(actions[0].run_call_count, 1);
+        assert_eq!(actions[1].run_call_count, 1);
+        assert_eq!(last_action_index, 1);
+    }
+
+    #[test]
+    fn test_skipped_action_does_not_consume_slot() {
+        let mut actions = vec![MockAction::from(Trigger {
+            percentage: Some(0.2),
+            action: Action {
+                active_hours: Some(ActiveHours {
+                    start: NaiveTime::from_hms_opt(8, 0, 0).unwrap(),
+                    end: NaiveTime::from_hms_opt(22, 0, 0).unwrap(),
+                }),
+                ..Default::default()
+            },
+            ..Default::default()
+        })];
+        let mut last_action_index = usize::MAX;
+        let start = Instant::now();
+
+        let clock = FakeClock::at_local(start, "2025-06-25 07:59");
+        match_actions(
+            &mut actions,
+            &0.15,
+            &mut last_action_index,
+            &clock,
+            &FORMAT_OBJ,
+        )
+        .unwrap();
+        assert_eq!(actions[0].run_call_count, 0);
+        assert_eq!(last_action_index, usize::MAX);
+
+        // Action is taken as soon as its schedule starts
+        let clock = FakeClock::at_local(start, "2025-06-25 08:00");
+        match_actions(
+            &mut actions,
+            &0.15,
+            &mut last_action_index,
+            &clock,
+            &FORMAT_OBJ,
+        )
+        .unwrap();
+        assert_eq!(actions[0].run_call_count, 1);
+    }
+
+    #[test]
+    fn test_actions_not_meeting_condition_are_skipped() {
+        let clock = FakeClock::at(Instant::now());
+        let mut actions = vec![
+            MockAction::from(Trigger {
+                percentage: Some(0.3),
+                action: Action {
+                    when: Some(expr::Expression::parse("energy_rate > 20").unwrap()),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }),
+            MockAction::from(Trigger {
+                percentage: Some(0.5),
+                ..Default::default()
+            }),
+        ];
+        let mut last_action_index = usize::MAX;
+        match_actions(
+            &mut actions,
+            &0.25,
+            &mut last_action_index,
+            &clock,
+            &FORMAT_OBJ,
+        )
+        .unwrap();
+        assert_eq!(actions[0].run_call_count, 0);
+        assert_eq!(actions[1].run_call_count, 1);
+
+        let format_obj = FormatObject {
+            energy_rate: &25.0,
+            ..FORMAT_OBJ
+        };
+        match_actions(
+            &mut actions,
+            &0.25,
+            &mut last_action_index,
+            &clock,
+            &format_obj,
+        )
+        .unwrap();
+        assert_eq!(actions[0].run_call_count, 1);
+        assert_eq!(actions[1].run_call_count, 1);
+    }
+
+    #[test]
+    fn test_when_expression_for_action() {
+        let action = Trigger {
+            percentage: Some(0.3),
+            action: Action {
+                when: Some(
+                    expr::Expression::parse("percentage < 30 && state == 'discharging'").unwrap(),
+                ),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let format_obj = FormatObject {
+            percentage: &25.0,
+            ..FORMAT_OBJ
+        };
+        assert!(action.condition_met(&format_obj));
+        let format_obj = FormatObject {
+            percentage: &35.0,
+            ..FORMAT_OBJ
+        };
+        assert!(!action.condition_met(&format_obj));
+    }
+
+    #[test]
+    fn test_events_only_run_matching_triggers() {
+        let mut triggers = vec![
+            Trigger {
+                on: Event::AcDisconnected,
+                action: Action {
+                    command: Some(Command::Args(vec![String::from("false")])),
+                    on_failure: Some(OnFailure::Exit),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            Trigger {
+                on: Event::Full,
+                action: Action {
+                    command: Some(Command::Args(vec![String::from("true")])),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        ];
+        let clock = FakeClock::at(Instant::now());
+
+        let result = match_events(&mut triggers, Event::AcConnected, &1.0, &clock, &FORMAT_OBJ);
+        assert!(result.is_ok());
+        let result = match_events(&mut triggers, Event::Full, &1.0, &clock, &FORMAT_OBJ);
+        assert!(result.is_ok());
+        let result = match_events(
+            &mut triggers,
+            Event::AcDisconnected,
+            &1.0,
+            &clock,
+            &FORMAT_OBJ,
+        );
+        assert!(result.is_ok());
+        assert!(wait_for_commands(&mut triggers).is_err());
+    }
+
+    #[test]
+    fn test_events_with_percentage() {
+        let mut triggers = vec![
+            Trigger {
+                on: Event::AcConnected,
+                percentage: Some(0.5),
+                ..Default::default()
+            },
+            Trigger {
+                on: Event::AcConnected,
+                ..Default::default()
+            },
+        ];
+        assert!(!triggers[0].exceeds_threshold(&0.4));
+        assert!(triggers[0].exceeds_threshold(&0.5));
+        assert!(triggers[1].exceeds_threshold(&0.0));
+
+        let format_obj = FormatObject {
+            percentage: &40.0,
+            ..FORMAT_OBJ
+        };
+        let clock = FakeClock::at(Instant::now());
+        triggers[0].action.command = Some(Command::Args(vec![String::from("false")]));
+        triggers[0].action.on_failure = Some(OnFailure::Exit);
+        match_events(&mut triggers, Event::AcConnected, &0.4, &clock, &format_obj).unwrap();
+        assert!(wait_for_commands(&mut triggers).is_ok());
+    }
+
+    #[test]
+    fn test_above_trigger() {
+        let mut triggers = vec![Trigger {
+            on: Event::Above,
+            percentage: Some(0.8),
+            hysteresis: Some(0.05),
+            ..Default::default()
+        }];
+        let clock = FakeClock::at(Instant::now());
+
+        match_above(&mut triggers, &0.75, &clock, &FORMAT_OBJ).unwrap();
+        assert!(!triggers[0].fired);
+        match_above(&mut triggers, &0.81, &clock, &FORMAT_OBJ).unwrap();
+        assert!(triggers[0].fired);
+
+        // Battery level has to drop below threshold minus hysteresis to re-arm
+        match_above(&mut triggers, &0.79, &clock, &FORMAT_OBJ).unwrap();
+        assert!(triggers[0].fired);
+        match_above(&mut triggers, &0.75, &clock, &FORMAT_OBJ).unwrap();
+        assert!(!triggers[0].fired);
+    }
+
+    #[test]
+    fn test_successful_action() {
+        let mut action = Trigger {
+            percentage: Some(0.5),
+            action: Action {
+                command: Some(Command::Args(vec![String::from("true")])),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let format_obj = FormatObject {
+            percentage: &50.0,
+            ..FORMAT_OBJ
+        };
+        let result = trigger_action(&mut action, &format_obj);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_no_action() {
+        let mut action = Trigger {
+            percentage: Some(0.5),
+            ..Default::default()
+        };
+        let format_obj = FormatObject {
+            percentage: &50.0,
+            ..FORMAT_OBJ
+        };
+        let result = trigger_action(&mut action, &format_obj);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_failing_action() {
+        let mut action = Trigger {
+            percentage: Some(0.5),
+            action: Action {
+                command: Some(Command::Args(vec![String::from("false")])),
+                on_failure: Some(OnFailure::Exit),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let format_obj = FormatObject {
+            percentage: &50.0,
+            ..FORMAT_OBJ
+        };
+        let result = trigger_action(&mut action, &format_obj);
+        assert!(result.is_ok());
+        assert!(wait_for_commands(slice::from_mut(&mut action)).is_err());
+    }
+
+    #[test]
+    fn test_successful_on_ac_action() {
+        let mut action = Trigger {
+            on: Event::AcConnected,
+            percentage: Some(0.0),
+            action: Action {
+                command: Some(Command::Args(vec![String::from("true")])),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let format_obj = FormatObject {
+            percentage: &50.0,
+            ..FORMAT_OBJ
+        };
+        let result = trigger_action(&mut action, &format_obj);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_no_on_ac_action() {
+        let mut action = Trigger {
+            on: Event::AcConnected,
+            percentage: Some(0.1),
+            ..Default::default()
+        };
+        let format_obj = FormatObject {
+            percentage: &50.0,
+            ..FORMAT_OBJ
+        };
         let result = trigger_action(&mut action, &format_obj);
         assert!(result.is_ok());
     }
 
     #[test]
     fn test_failing_on_ac_action() {
-        let mut action = OnAcAction {
-            percentage: 0.0,
-            notify: None,
-            command: Some(vec![String::from("false")]),
+        let mut action = Trigger {
+            on: Event::AcConnected,
+            percentage: Some(0.0),
+            action: Action {
+                command: Some(Command::Args(vec![String::from("false")])),
+                on_failure: Some(OnFailure::Exit),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let format_obj = FormatObject {
+            percentage: &50.0,
+            ..FORMAT_OBJ
         };
-        let format_obj = FormatObject { percentage: &50.0 };
         let result = trigger_action(&mut action, &format_obj);
-        assert!(result.is_err());
+        assert!(result.is_ok());
+        assert!(wait_for_commands(slice::from_mut(&mut action)).is_err());
     }
 
     #[test]
     fn test_template_replaces_percentage() {
         let summary = String::from("Percentage is $percentage%!");
         let body = String::from("$percentage is also in the body");
-        let action_w_notify = Action {
-            percentage: 0.5,
-            command: None,
-            notify: Some(Notify {
-                summary: summary.clone(),
-                body: Some(body.clone()),
-                urgency: Urgency::Low,
-                icon: String::from(""),
-                timeout: Timeout::Default,
-            }),
+        let action_w_notify = Trigger {
+            percentage: Some(0.5),
+            action: Action {
+                notify: Some(Notify {
+                    summary: summary.clone(),
+                    body: Some(body.clone()),
+                    urgency: Urgency::Low,
+                    icon: String::from(""),
+                    timeout: Timeout::Default,
+                }),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let format_obj = FormatObject {
+            percentage: &42.0,
+            ..FORMAT_OBJ
         };
-        let format_obj = FormatObject { percentage: &42.0 };
         let summary_result = action_w_notify.fill_template(summary, &format_obj);
         assert_eq!(summary_result, "Percentage is 42%!");
         let body_result = action_w_notify.fill_template(body, &format_obj);
@@ -580,18 +2971,25 @@ mod tests {
     fn test_template_replaces_percentage_for_on_ac_action() {
         let summary = String::from("Percentage is $percentage%!");
         let body = String::from("$percentage is also in the body");
-        let action_w_notify = OnAcAction {
-            percentage: 0.21,
-            command: None,
-            notify: Some(Notify {
-                summary: summary.clone(),
-                body: Some(body.clone()),
-                urgency: Urgency::Low,
-                icon: String::from(""),
-                timeout: Timeout::Default,
-            }),
+        let action_w_notify = Trigger {
+            on: Event::AcConnected,
+            percentage: Some(0.21),
+            action: Action {
+                notify: Some(Notify {
+                    summary: summary.clone(),
+                    body: Some(body.clone()),
+                    urgency: Urgency::Low,
+                    icon: String::from(""),
+                    timeout: Timeout::Default,
+                }),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let format_obj = FormatObject {
+            percentage: &42.0,
+            ..FORMAT_OBJ
         };
-        let format_obj = FormatObject { percentage: &42.0 };
         let summary_result = action_w_notify.fill_template(summary, &format_obj);
         assert_eq!(summary_result, "Percentage is 42%!");
         let body_result = action_w_notify.fill_template(body, &format_obj);
@@ -601,18 +2999,24 @@ mod tests {
     #[test]
     fn test_template_replaces_nothing() {
         let summary = String::from("No percentage to replace here!");
-        let action_w_notify = Action {
-            percentage: 0.5,
-            command: None,
-            notify: Some(Notify {
-                summary: summary.clone(),
-                body: None,
-                urgency: Urgency::Low,
-                icon: String::from(""),
-                timeout: Timeout::Default,
-            }),
+        let action_w_notify = Trigger {
+            percentage: Some(0.5),
+            action: Action {
+                notify: Some(Notify {
+                    summary: summary.clone(),
+                    body: None,
+                    urgency: Urgency::Low,
+                    icon: String::from(""),
+                    timeout: Timeout::Default,
+                }),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let format_obj = FormatObject {
+            percentage: &42.0,
+            ..FORMAT_OBJ
         };
-        let format_obj = FormatObject { percentage: &42.0 };
         let result = action_w_notify.fill_template(summary, &format_obj);
         assert_eq!(result, "No percentage to replace here!");
     }
@@ -620,22 +3024,375 @@ mod tests {
     #[test]
     fn test_template_does_not_replace_unknown() {
         let summary = String::from("No $value to replace here!");
-        let action_w_notify = Action {
-            percentage: 0.5,
-            command: None,
-            notify: Some(Notify {
-                summary: summary.clone(),
-                body: None,
-                urgency: Urgency::Low,
-                icon: String::from(""),
-                timeout: Timeout::Default,
-            }),
+        let action_w_notify = Trigger {
+            percentage: Some(0.5),
+            action: Action {
+                notify: Some(Notify {
+                    summary: summary.clone(),
+                    body: None,
+                    urgency: Urgency::Low,
+                    icon: String::from(""),
+                    timeout: Timeout::Default,
+                }),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let format_obj = FormatObject {
+            percentage: &42.0,
+            ..FORMAT_OBJ
         };
-        let format_obj = FormatObject { percentage: &42.0 };
         let result = action_w_notify.fill_template(summary, &format_obj);
         assert_eq!(result, "No $value to replace here!");
     }
 
+    #[test]
+    fn test_power_draw_sustained() {
+        let action = PowerDrawAction {
+            watts: 30.0,
+            duration: Duration::from_secs(300),
+            action: Action::default(),
+            above_since: None,
+            fired: false,
+        };
+        let mut actions = vec![action];
+        let start = Instant::now();
+
+        // Power draw is above threshold, but not for long enough yet
+        let result = match_power_draw(&mut actions, &35.0, &FakeClock::at(start), &FORMAT_OBJ);
+        assert!(result.is_ok());
+        assert_eq!(actions[0].above_since, Some(start));
+        assert!(!actions[0].fired);
+
+        let result = match_power_draw(
+            &mut actions,
+            &35.0,
+            &FakeClock::at(start + Duration::from_secs(299)),
+            &FORMAT_OBJ,
+        );
+        assert!(result.is_ok());
+        assert!(!actions[0].fired);
+
+        // Power draw has been sustained for the configured duration
+        let result = match_power_draw(
+            &mut actions,
+            &35.0,
+            &FakeClock::at(start + Duration::from_secs(300)),
+            &FORMAT_OBJ,
+        );
+        assert!(result.is_ok());
+        assert!(actions[0].fired);
+    }
+
+    #[test]
+    fn test_power_draw_drop_resets_window() {
+        let action = PowerDrawAction {
+            watts: 30.0,
+            duration: Duration::from_secs(300),
+            action: Action::default(),
+            above_since: None,
+            fired: false,
+        };
+        let mut actions = vec![action];
+        let start = Instant::now();
+
+        match_power_draw(&mut actions, &35.0, &FakeClock::at(start), &FORMAT_OBJ).unwrap();
+        match_power_draw(
+            &mut actions,
+            &12.0,
+            &FakeClock::at(start + Duration::from_secs(200)),
+            &FORMAT_OBJ,
+        )
+        .unwrap();
+        assert_eq!(actions[0].above_since, None);
+
+        match_power_draw(
+            &mut actions,
+            &35.0,
+            &FakeClock::at(start + Duration::from_secs(300)),
+            &FORMAT_OBJ,
+        )
+        .unwrap();
+        assert!(!actions[0].fired);
+        assert_eq!(
+            actions[0].above_since,
+            Some(start + Duration::from_secs(300))
+        );
+    }
+
+    #[test]
+    fn test_failing_power_draw_action() {
+        let clock = FakeClock::at(Instant::now());
+        let action = PowerDrawAction {
+            watts: 30.0,
+            duration: Duration::from_secs(0),
+            action: Action {
+                command: Some(Command::Args(vec![String::from("false")])),
+                on_failure: Some(OnFailure::Exit),
+                ..Default::default()
+            },
+            above_since: None,
+            fired: false,
+        };
+        let mut actions = vec![action];
+        let result = match_power_draw(&mut actions, &35.0, &clock, &FORMAT_OBJ);
+        assert!(result.is_ok());
+        assert!(wait_for_commands(&mut actions).is_err());
+    }
+
+    #[test]
+    fn test_sudden_drop() {
+        let mut actions = vec![DropAction {
+            by: 0.2,
+            within: Duration::from_secs(600),
+            action: Action::default(),
+            fired: false,
+        }];
+        let mut history = ChargeHistory::new(Duration::from_secs(600));
+        let start = Instant::now();
+        for (seconds, charge_value) in [(0, 0.32), (60, 0.3), (120, 0.2)] {
+            let now = start + Duration::from_secs(seconds);
+            history.record(now, charge_value);
+            match_drop(&mut actions, &history, &FakeClock::at(now), &FORMAT_OBJ).unwrap();
+        }
+        assert!(!actions[0].fired);
+
+        let now = start + Duration::from_secs(180);
+        history.record(now, 0.08);
+        match_drop(&mut actions, &history, &FakeClock::at(now), &FORMAT_OBJ).unwrap();
+        assert!(actions[0].fired);
+
+        // Action is armed again once the drop left the window
+        let now = start + Duration::from_secs(900);
+        history.record(now, 0.07);
+        match_drop(&mut actions, &history, &FakeClock::at(now), &FORMAT_OBJ).unwrap();
+        assert!(!actions[0].fired);
+    }
+
+    #[test]
+    fn test_drop_condition() {
+        let mut actions = vec![DropAction {
+            by: 0.1,
+            within: Duration::from_secs(600),
+            action: Action {
+                when: Some(expr::Expression::parse("drop_rate > 10").unwrap()),
+                ..Default::default()
+            },
+            fired: false,
+        }];
+        let mut history = ChargeHistory::new(Duration::from_secs(600));
+        let start = Instant::now();
+        history.record(start, 0.5);
+        let now = start + Duration::from_secs(120);
+        history.record(now, 0.35); // 7.5 points per minute
+        match_drop(&mut actions, &history, &FakeClock::at(now), &FORMAT_OBJ).unwrap();
+        assert!(!actions[0].fired);
+
+        let now = start + Duration::from_secs(180);
+        history.record(now, 0.1); // 13.3 points per minute
+        match_drop(&mut actions, &history, &FakeClock::at(now), &FORMAT_OBJ).unwrap();
+        assert!(actions[0].fired);
+    }
+
+    #[test]
+    fn test_failing_drop_action() {
+        let mut actions = vec![DropAction {
+            by: 0.1,
+            within: Duration::from_secs(300),
+            action: Action {
+                command: Some(Command::Args(vec![String::from("false")])),
+                on_failure: Some(OnFailure::Exit),
+                ..Default::default()
+            },
+            fired: false,
+        }];
+        let mut history = ChargeHistory::new(Duration::from_secs(300));
+        let start = Instant::now();
+        history.record(start, 0.5);
+        let result = match_drop(&mut actions, &history, &FakeClock::at(start), &FORMAT_OBJ);
+        assert!(result.is_ok());
+
+        history.record(start + Duration::from_secs(60), 0.35);
+        let result = match_drop(
+            &mut actions,
+            &history,
+            &FakeClock::at(start + Duration::from_secs(60)),
+            &FORMAT_OBJ,
+        );
+        assert!(result.is_ok());
+        assert!(wait_for_commands(&mut actions).is_err());
+    }
+
+    #[test]
+    fn test_temperature_hysteresis() {
+        let clock = FakeClock::at(Instant::now());
+        let action = TemperatureAction {
+            above: 50.0,
+            hysteresis: 2.0,
+            action: Action::default(),
+            fired: false,
+        };
+        let mut actions = vec![action];
+
+        match_temperature(&mut actions, &45.0, &clock, &FORMAT_OBJ).unwrap();
+        assert!(!actions[0].fired);
+
+        match_temperature(&mut actions, &50.5, &clock, &FORMAT_OBJ).unwrap();
+        assert!(actions[0].fired);
+
+        // Temperature fluctuating around threshold must not re-arm the action
+        match_temperature(&mut actions, &49.5, &clock, &FORMAT_OBJ).unwrap();
+        assert!(actions[0].fired);
+        match_temperature(&mut actions, &50.5, &clock, &FORMAT_OBJ).unwrap();
+        assert!(actions[0].fired);
+
+        // Action is armed again once temperature dropped below hysteresis
+        match_temperature(&mut actions, &47.5, &clock, &FORMAT_OBJ).unwrap();
+        assert!(!actions[0].fired);
+    }
+
+    #[test]
+    fn test_failing_temperature_action() {
+        let clock = FakeClock::at(Instant::now());
+        let action = TemperatureAction {
+            above: 50.0,
+            hysteresis: 2.0,
+            action: Action {
+                command: Some(Command::Args(vec![String::from("false")])),
+                on_failure: Some(OnFailure::Exit),
+                ..Default::default()
+            },
+            fired: false,
+        };
+        let mut actions = vec![action];
+        let result = match_temperature(&mut actions, &55.0, &clock, &FORMAT_OBJ);
+        assert!(result.is_ok());
+        assert!(wait_for_commands(&mut actions).is_err());
+    }
+
+    #[test]
+    fn test_temperature_action_conditions() {
+        let clock = FakeClock::at(Instant::now());
+        let mut actions = vec![TemperatureAction {
+            above: 50.0,
+            hysteresis: 2.0,
+            action: Action {
+                enabled: Some(false),
+                when: Some(expr::Expression::parse("health < 90").unwrap()),
+                ..Default::default()
+            },
+            fired: false,
+        }];
+        match_temperature(&mut actions, &55.0, &clock, &FORMAT_OBJ).unwrap();
+        assert!(!actions[0].fired);
+
+        // Still skipped while the condition isn't met
+        actions[0].action.enabled = None;
+        match_temperature(&mut actions, &55.0, &clock, &FORMAT_OBJ).unwrap();
+        assert!(!actions[0].fired);
+
+        let format_obj = FormatObject {
+            health: &85.0,
+            ..FORMAT_OBJ
+        };
+        match_temperature(&mut actions, &55.0, &clock, &format_obj).unwrap();
+        assert!(actions[0].fired);
+    }
+
+    #[test]
+    fn test_toggled_temperature_action() {
+        let clock = FakeClock::at(Instant::now());
+        let mut temperature_actions = vec![TemperatureAction {
+            above: 50.0,
+            hysteresis: 2.0,
+            action: Action {
+                name: Some(String::from("cooldown")),
+                ..Default::default()
+            },
+            fired: false,
+        }];
+        assert_eq!(temperature_actions[0].name(), Some("cooldown"));
+        let mut toggles = state::Toggles::default();
+        toggles.set("cooldown", false);
+        for runner in all_actions(
+            &mut [],
+            &mut [],
+            &mut [],
+            &mut [],
+            &mut temperature_actions,
+            &mut [],
+        ) {
+            let action = runner.action_mut();
+            action.toggled = toggles.lookup(action.selectors());
+        }
+        match_temperature(&mut temperature_actions, &55.0, &clock, &FORMAT_OBJ).unwrap();
+        assert!(!temperature_actions[0].fired);
+    }
+
+    #[test]
+    fn test_health_action_below_threshold() {
+        let clock = FakeClock::at(Instant::now());
+        let action = HealthAction {
+            below: Some(0.8),
+            cycle_count: None,
+            action: Action {
+                command: Some(Command::Args(vec![String::from("false")])),
+                on_failure: Some(OnFailure::Exit),
+                ..Default::default()
+            },
+        };
+        let mut actions = vec![action];
+
+        // Battery is still healthy, so the failing command is not run
+        let result = match_health(&mut actions, &0.85, Some(2000), &clock, &FORMAT_OBJ);
+        assert!(result.is_ok());
+
+        let result = match_health(&mut actions, &0.75, None, &clock, &FORMAT_OBJ);
+        assert!(result.is_ok());
+        assert!(wait_for_commands(&mut actions).is_err());
+    }
+
+    #[test]
+    fn test_health_action_cycle_count() {
+        let clock = FakeClock::at(Instant::now());
+        let action = HealthAction {
+            below: None,
+            cycle_count: Some(1000),
+            action: Action {
+                command: Some(Command::Args(vec![String::from("false")])),
+                on_failure: Some(OnFailure::Exit),
+                ..Default::default()
+            },
+        };
+        let mut actions = vec![action];
+
+        let result = match_health(&mut actions, &0.5, Some(1000), &clock, &FORMAT_OBJ);
+        assert!(result.is_ok());
+        let result = match_health(&mut actions, &0.5, None, &clock, &FORMAT_OBJ);
+        assert!(result.is_ok());
+
+        let result = match_health(&mut actions, &0.5, Some(1001), &clock, &FORMAT_OBJ);
+        assert!(result.is_ok());
+        assert!(wait_for_commands(&mut actions).is_err());
+    }
+
+    #[test]
+    fn test_template_replaces_overlapping_placeholders() {
+        let summary = String::from("$energy_full of $energy_full_design Wh");
+        let action = HealthAction {
+            below: Some(0.8),
+            cycle_count: None,
+            action: Action::default(),
+        };
+        let format_obj = FormatObject {
+            energy_full: &40.5,
+            energy_full_design: &57.0,
+            ..FORMAT_OBJ
+        };
+        let result = action.fill_template(summary, &format_obj);
+        assert_eq!(result, "40.5 of 57 Wh");
+    }
+
     #[test]
     fn test_get_config_from_invalid_path() {
         let result = get_config(&PathBuf::from("/dev/null"));
@@ -646,6 +3403,18 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_get_config_without_triggers() {
+        let path = std::env::temp_dir().join(format!("battered-test-{}.toml", std::process::id()));
+        std::fs::write(&path, "[[temperature]]\nabove = 50.0\n").unwrap();
+        let result = get_config(&path);
+        std::fs::remove_file(&path).ok();
+        // Other kinds of actions don't need any trigger
+        let config = result.unwrap();
+        assert!(config.action.is_empty() && config.trigger.is_empty());
+        assert_eq!(config.temperature.len(), 1);
+    }
+
     #[test]
     fn test_pick_battery_by_serial_not_found() {
         let manager = starship_battery::Manager::new().unwrap();
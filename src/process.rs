@@ -0,0 +1,488 @@
+use crate::config::SystemAction;
+use anyhow::{Context, Result};
+use std::collections::VecDeque;
+use std::io::Read;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// Time a command gets to exit after SIGTERM before it gets killed
+const KILL_GRACE: Duration = Duration::from_secs(5);
+// Time to finish reading output after the command exited, e.g. if it left a child behind
+const OUTPUT_GRACE: Duration = Duration::from_secs(1);
+// Output beyond this many bytes per stream is discarded
+const MAX_OUTPUT: usize = 64 * 1024;
+
+// Runtime state of the commands of an action
+#[derive(Debug, Default)]
+pub struct CommandState {
+    // Commands running in the background
+    pub processes: Vec<Process>,
+    // Commands waiting for the previous one to finish, and their environment
+    pub queue: VecDeque<Vec<String>>,
+    pub env: Vec<(String, String)>,
+    // System action waiting for the commands to finish, set once per trigger, not on retries
+    pub system: Option<SystemAction>,
+    // Failures of this run, reported once all commands finished
+    pub errors: Vec<anyhow::Error>,
+    // Number of times the failed command was run again
+    pub retries: u32,
+    // When to run the failed command again
+    pub retry_at: Option<Instant>,
+    // Action was disabled after its command failed
+    pub disabled: bool,
+    // Output of the last run of the commands
+    pub output: Option<Output>,
+    // Notification is shown once the command finished, as it shows its output
+    pub notify_pending: bool,
+}
+
+impl CommandState {
+    pub fn is_running(&self) -> bool {
+        !self.processes.is_empty() || !self.queue.is_empty()
+    }
+
+    // System action to take now that all commands finished, dropped if any of them failed
+    pub fn take_system(&mut self) -> Option<SystemAction> {
+        if self.is_running() {
+            return None;
+        }
+        let system = self.system.take()?;
+        if !self.errors.is_empty() {
+            warn!("Skipping system action '{}' as commands failed", system);
+            return None;
+        }
+        Some(system)
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Output {
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl Output {
+    // Both streams, stdout first
+    pub fn combined(&self) -> String {
+        [self.stdout.as_str(), self.stderr.as_str()]
+            .into_iter()
+            .filter(|stream| !stream.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    // Add output of the next command, one after the other
+    pub fn append(&mut self, other: Output) {
+        for (output, other) in [
+            (&mut self.stdout, other.stdout),
+            (&mut self.stderr, other.stderr),
+        ] {
+            if !output.is_empty() && !other.is_empty() {
+                output.push('\n');
+            }
+            output.push_str(&other);
+        }
+    }
+}
+
+// Reads a stream in the background, so the command never blocks on a full pipe
+#[derive(Debug)]
+struct Capture {
+    buffer: Arc<Mutex<Vec<u8>>>,
+    reader: JoinHandle<()>,
+}
+
+impl Capture {
+    fn new<R: Read + Send + 'static>(mut stream: R) -> Capture {
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let shared = Arc::clone(&buffer);
+        let reader = thread::spawn(move || {
+            let mut chunk = [0; 4096];
+            while let Ok(n @ 1..) = stream.read(&mut chunk) {
+                let mut buffer = shared.lock().unwrap();
+                let n = n.min(MAX_OUTPUT.saturating_sub(buffer.len()));
+                buffer.extend_from_slice(&chunk[..n]);
+            }
+        });
+        Capture { buffer, reader }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.reader.is_finished()
+    }
+
+    fn text(&self) -> String {
+        let buffer = self.buffer.lock().unwrap();
+        String::from_utf8_lossy(&buffer).trim_end().to_string()
+    }
+}
+
+// Command running in the background, in a process group of its own
+#[derive(Debug)]
+pub struct Process {
+    child: Child,
+    command: String,
+    timeout: Option<Duration>,
+    // When to send the next signal; SIGTERM once timed out, SIGKILL once terminated
+    deadline: Option<Instant>,
+    terminated: bool,
+    // Terminated by `stop` instead of timing out
+    stopped: bool,
+    stdout: Capture,
+    stderr: Capture,
+    status: Option<ExitStatus>,
+    exited_at: Option<Instant>,
+}
+
+impl Process {
+    pub fn spawn(
+        command: &[String],
+        env: &[(String, String)],
+        cwd: Option<&Path>,
+        timeout: Option<Duration>,
+    ) -> Result<Process> {
+        let mut program = PathBuf::from(&command[0]);
+        // Paths like `./powersave.sh` are relative to `cwd`, names are looked up in PATH
+        if let Some(cwd) = cwd.filter(|_| program.is_relative() && program.components().count() > 1)
+        {
+            program = cwd.join(program);
+        }
+        let mut process = Command::new(program);
+        if let Some(cwd) = cwd {
+            process.current_dir(cwd);
+        }
+        let mut child = process
+            .args(&command[1..])
+            .envs(env.iter().map(|(key, value)| (key, value)))
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .process_group(0)
+            .spawn()
+            .with_context(|| format!("Failed to execute '{}'", command.join(" ")))?;
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let stderr = child.stderr.take().expect("stderr is piped");
+        Ok(Process {
+            child,
+            command: command.join(" "),
+            timeout,
+            deadline: timeout.map(|timeout| Instant::now() + timeout),
+            terminated: false,
+            stopped: false,
+            stdout: Capture::new(stdout),
+            stderr: Capture::new(stderr),
+            status: None,
+            exited_at: None,
+        })
+    }
+
+    // Output captured so far
+    pub fn output(&self) -> Output {
+        Output {
+            stdout: self.stdout.text(),
+            stderr: self.stderr.text(),
+        }
+    }
+
+    // Exit status once the command exited
+    pub fn status(&self) -> Option<ExitStatus> {
+        self.status
+    }
+
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    // Returns the result once the command exited, stopping it if it timed out
+    pub fn poll(&mut self, now: Instant) -> Option<Result<()>> {
+        if self.exited_at.is_some() {
+            return self.finish();
+        }
+        match self.child.try_wait() {
+            Ok(Some(status)) => {
+                self.status = Some(status);
+                self.exited_at = Some(Instant::now());
+                return self.finish();
+            }
+            Ok(None) => (),
+            Err(e) => return Some(Err(anyhow::Error::from(e))),
+        }
+        match self.deadline {
+            Some(deadline) if now >= deadline && !self.terminated => {
+                warn!("Command '{}' timed out, terminating it", self.command);
+                self.signal(libc::SIGTERM);
+                self.terminated = true;
+                self.deadline = Some(now + KILL_GRACE);
+                None
+            }
+            Some(deadline) if now >= deadline => {
+                warn!("Command '{}' did not terminate, killing it", self.command);
+                self.signal(libc::SIGKILL);
+                self.status = self.child.wait().ok();
+                Some(Err(self.terminated_error()))
+            }
+            _ => None,
+        }
+    }
+
+    // Result of the exited command, once its output has been read
+    fn finish(&self) -> Option<Result<()>> {
+        let reading = !self.stdout.is_finished() || !self.stderr.is_finished();
+        if reading
+            && self
+                .exited_at
+                .is_some_and(|exited_at| exited_at.elapsed() < OUTPUT_GRACE)
+        {
+            return None;
+        }
+        match self.status {
+            _ if self.terminated => Some(Err(self.terminated_error())),
+            Some(status) if !status.success() => Some(Err(anyhow::anyhow!(
+                "Command '{}' failed: {}",
+                self.command,
+                status
+            ))),
+            _ => Some(Ok(())),
+        }
+    }
+
+    // Time until the command has to be checked on again, if it has a timeout
+    pub fn next_poll(&self, now: Instant) -> Option<Duration> {
+        if self.exited_at.is_some() {
+            return Some(Duration::from_millis(100)); // Still reading output
+        }
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(now))
+    }
+
+    // Terminate the command before it finished, e.g. once another command of the action failed
+    pub fn stop(&mut self, now: Instant) {
+        if self.terminated || self.exited_at.is_some() {
+            return;
+        }
+        warn!("Stopping command '{}'", self.command);
+        self.signal(libc::SIGTERM);
+        self.terminated = true;
+        self.stopped = true;
+        self.deadline = Some(now + KILL_GRACE);
+    }
+
+    // Signal the whole process group, so that children of scripts get stopped as well
+    fn signal(&self, signal: libc::c_int) {
+        let process_group = self.child.id() as libc::pid_t;
+        // SAFETY: kill() has no memory safety requirements
+        unsafe {
+            libc::kill(-process_group, signal);
+        }
+    }
+
+    fn terminated_error(&self) -> anyhow::Error {
+        if self.stopped {
+            return anyhow::anyhow!("Command '{}' was stopped", self.command);
+        }
+        anyhow::anyhow!(
+            "Command '{}' timed out after {}s",
+            self.command,
+            self.timeout.unwrap_or_default().as_secs()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_take_system() {
+        let mut state = CommandState {
+            system: Some(SystemAction::Suspend),
+            ..Default::default()
+        };
+        assert_eq!(state.take_system(), Some(SystemAction::Suspend));
+        assert_eq!(state.take_system(), None); // Taken once
+
+        // Waits for queued commands, and is dropped once any of them failed
+        state.system = Some(SystemAction::Suspend);
+        state.queue.push_back(vec![String::from("true")]);
+        assert_eq!(state.take_system(), None);
+        state.queue.clear();
+        state.errors.push(anyhow::anyhow!("Command 'false' failed"));
+        assert_eq!(state.take_system(), None);
+        assert_eq!(state.system, None);
+    }
+
+    fn command(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    // Poll at the given time until the command exited or a signal was sent
+    fn poll_until(process: &mut Process, now: Instant) -> Option<Result<()>> {
+        for _ in 0..200 {
+            let terminated = process.terminated;
+            if let Some(result) = process.poll(now) {
+                return Some(result);
+            }
+            if process.terminated != terminated {
+                return None;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("Command '{}' did not finish", process.command);
+    }
+
+    #[test]
+    fn test_successful_command() {
+        let mut process = Process::spawn(&command(&["true"]), &[], None, None).unwrap();
+        let result = poll_until(&mut process, Instant::now());
+        assert!(result.unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_failing_command() {
+        let mut process = Process::spawn(&command(&["false"]), &[], None, None).unwrap();
+        let result = poll_until(&mut process, Instant::now());
+        assert!(result
+            .unwrap()
+            .unwrap_err()
+            .to_string()
+            .starts_with("Command 'false' failed"));
+    }
+
+    #[test]
+    fn test_output_is_captured() {
+        let script = command(&["sh", "-c", "echo started; echo 'no such file' >&2; exit 2"]);
+        let mut process = Process::spawn(&script, &[], None, None).unwrap();
+        assert!(poll_until(&mut process, Instant::now()).unwrap().is_err());
+        assert_eq!(process.status().and_then(|status| status.code()), Some(2));
+        let output = process.output();
+        assert_eq!(output.stdout, "started");
+        assert_eq!(output.stderr, "no such file");
+        assert_eq!(output.combined(), "started\nno such file");
+    }
+
+    #[test]
+    fn test_large_output_is_capped() {
+        let script = command(&["head", "-c", "1000000", "/dev/zero"]);
+        let mut process = Process::spawn(&script, &[], None, None).unwrap();
+        assert!(poll_until(&mut process, Instant::now()).unwrap().is_ok());
+        assert_eq!(process.output().stdout.len(), MAX_OUTPUT);
+    }
+
+    #[test]
+    fn test_output_of_command_leaving_child_behind() {
+        // Child keeps the pipe open, which must not keep the command from finishing
+        let script = command(&["sh", "-c", "sleep 30 & echo done"]);
+        let mut process = Process::spawn(&script, &[], None, None).unwrap();
+        assert!(poll_until(&mut process, Instant::now()).unwrap().is_ok());
+        assert_eq!(process.output().stdout, "done");
+        process.signal(libc::SIGKILL);
+    }
+
+    #[test]
+    fn test_command_in_cwd() {
+        let cwd = std::env::temp_dir().join(format!("battered-test-cwd-{}", std::process::id()));
+        std::fs::create_dir_all(&cwd).unwrap();
+        let script = cwd.join("powersave.sh");
+        std::fs::write(&script, "#!/bin/sh\npwd\n").unwrap();
+        std::fs::set_permissions(&script, std::os::unix::fs::PermissionsExt::from_mode(0o755))
+            .unwrap();
+
+        // Relative path of the command is resolved against `cwd`
+        let mut process =
+            Process::spawn(&command(&["./powersave.sh"]), &[], Some(&cwd), None).unwrap();
+        let result = poll_until(&mut process, Instant::now());
+        std::fs::remove_dir_all(&cwd).ok();
+        assert!(result.unwrap().is_ok());
+        assert_eq!(process.output().stdout, cwd.display().to_string());
+    }
+
+    #[test]
+    fn test_stop_command() {
+        let mut process = Process::spawn(&command(&["sleep", "30"]), &[], None, None).unwrap();
+        let now = Instant::now();
+        process.stop(now);
+        assert_eq!(process.next_poll(now), Some(KILL_GRACE));
+        let result = poll_until(&mut process, now);
+        assert_eq!(
+            result.unwrap().unwrap_err().to_string(),
+            "Command 'sleep 30' was stopped"
+        );
+    }
+
+    #[test]
+    fn test_append_output() {
+        let mut output = Output::default();
+        output.append(Output {
+            stdout: String::from("dimmed"),
+            stderr: String::new(),
+        });
+        output.append(Output {
+            stdout: String::from("stopped sync"),
+            stderr: String::from("no such unit"),
+        });
+        assert_eq!(output.stdout, "dimmed\nstopped sync");
+        assert_eq!(output.stderr, "no such unit");
+    }
+
+    #[test]
+    fn test_missing_command() {
+        let result = Process::spawn(&command(&["battered-missing-command"]), &[], None, None);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Failed to execute 'battered-missing-command'"
+        );
+    }
+
+    #[test]
+    fn test_running_command() {
+        let mut process = Process::spawn(&command(&["sleep", "30"]), &[], None, None).unwrap();
+        assert!(process.poll(Instant::now()).is_none());
+        assert_eq!(process.next_poll(Instant::now()), None);
+        process.signal(libc::SIGKILL);
+        process.child.wait().unwrap();
+    }
+
+    #[test]
+    fn test_timeout_terminates_command() {
+        let timeout = Some(Duration::from_secs(10));
+        let mut process = Process::spawn(&command(&["sleep", "30"]), &[], None, timeout).unwrap();
+        let start = Instant::now();
+        assert!(process.poll(start).is_none());
+        assert!(process.next_poll(start).unwrap() <= Duration::from_secs(10));
+
+        // SIGTERM once timed out, then the command exits
+        assert!(poll_until(&mut process, start + Duration::from_secs(11)).is_none());
+        assert_eq!(
+            process.next_poll(start + Duration::from_secs(11)),
+            Some(KILL_GRACE)
+        );
+        let result = poll_until(&mut process, start + Duration::from_secs(11));
+        assert_eq!(
+            result.unwrap().unwrap_err().to_string(),
+            "Command 'sleep 30' timed out after 10s"
+        );
+    }
+
+    #[test]
+    fn test_timeout_kills_command_ignoring_sigterm() {
+        let timeout = Some(Duration::from_secs(10));
+        let script = command(&["sh", "-c", "trap '' TERM; sleep 30 & wait"]);
+        let mut process = Process::spawn(&script, &[], None, timeout).unwrap();
+        thread::sleep(Duration::from_millis(100)); // Let the trap get set up
+        let start = Instant::now();
+        assert!(poll_until(&mut process, start + Duration::from_secs(11)).is_none());
+        assert!(process.poll(start + Duration::from_secs(12)).is_none());
+
+        // SIGKILL once the grace period is over
+        let result = process.poll(start + Duration::from_secs(17));
+        assert!(result
+            .unwrap()
+            .unwrap_err()
+            .to_string()
+            .ends_with("timed out after 10s"));
+    }
+}
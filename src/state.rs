@@ -0,0 +1,194 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::mem;
+use std::path::Path;
+use std::time::SystemTime;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+// State which has to survive restarts of battered
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct PersistentState {
+    // Unix timestamp of the last battery health check
+    pub last_health_check: Option<u64>,
+    #[serde(default)]
+    pub toggles: Toggles,
+}
+
+// Actions enabled or disabled by name or tag, overriding their `enabled` setting
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Toggles {
+    #[serde(default)]
+    pub enabled: BTreeSet<String>,
+    #[serde(default)]
+    pub disabled: BTreeSet<String>,
+}
+
+impl Toggles {
+    pub fn set(&mut self, selector: &str, enabled: bool) {
+        let (add, remove) = match enabled {
+            true => (&mut self.enabled, &mut self.disabled),
+            false => (&mut self.disabled, &mut self.enabled),
+        };
+        remove.remove(selector);
+        add.insert(selector.to_string());
+    }
+
+    // Disabling wins if an action is matched by several selectors
+    pub fn lookup<'a>(&self, selectors: impl IntoIterator<Item = &'a str> + Clone) -> Option<bool> {
+        if selectors
+            .clone()
+            .into_iter()
+            .any(|s| self.disabled.contains(s))
+        {
+            Some(false)
+        } else if selectors.into_iter().any(|s| self.enabled.contains(s)) {
+            Some(true)
+        } else {
+            None
+        }
+    }
+}
+
+impl PersistentState {
+    pub fn load(path: &Path) -> Result<PersistentState> {
+        let state_values = match std::fs::read_to_string(path) {
+            Ok(state_values) => state_values,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(PersistentState::default())
+            }
+            Err(e) => return Err(anyhow::Error::from(e)),
+        };
+        toml::from_str(&state_values)
+            .with_context(|| format!("Failed to parse state at '{}'", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create '{}'", parent.display()))?;
+        }
+        let state_values = toml::to_string(self)?;
+        std::fs::write(path, state_values)
+            .with_context(|| format!("Failed to write state to '{}'", path.display()))
+    }
+
+    pub fn health_check_due(&self, now: u64) -> bool {
+        match self.last_health_check {
+            Some(last_check) => now.saturating_sub(last_check) >= SECONDS_PER_DAY,
+            None => true,
+        }
+    }
+}
+
+// Modification time of a file, so it is only reloaded after it changed
+#[derive(Debug, Default)]
+pub struct ModifiedTracker {
+    modified: Option<SystemTime>,
+}
+
+impl ModifiedTracker {
+    // Missing or unreadable files count as a version of their own
+    pub fn changed(&mut self, path: &Path) -> bool {
+        let modified = std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        mem::replace(&mut self.modified, modified) != modified
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_health_check_due() {
+        let state = PersistentState {
+            last_health_check: None,
+            ..Default::default()
+        };
+        assert!(state.health_check_due(1_000_000));
+
+        let state = PersistentState {
+            last_health_check: Some(1_000_000),
+            ..Default::default()
+        };
+        assert!(!state.health_check_due(1_000_000));
+        assert!(!state.health_check_due(1_000_000 + SECONDS_PER_DAY - 1));
+        assert!(state.health_check_due(1_000_000 + SECONDS_PER_DAY));
+    }
+
+    #[test]
+    fn test_health_check_due_with_clock_skew() {
+        // Clock going backwards must not panic or trigger checks
+        let state = PersistentState {
+            last_health_check: Some(1_000_000),
+            ..Default::default()
+        };
+        assert!(!state.health_check_due(500));
+    }
+
+    #[test]
+    fn test_load_missing_state() {
+        let path = std::env::temp_dir().join("battered-test-missing/state.toml");
+        let state = PersistentState::load(&path).unwrap();
+        assert_eq!(state, PersistentState::default());
+    }
+
+    #[test]
+    fn test_save_and_load_state() {
+        let dir = std::env::temp_dir().join(format!("battered-test-{}", std::process::id()));
+        let path = dir.join("state.toml");
+        let mut state = PersistentState {
+            last_health_check: Some(1_234_567),
+            ..Default::default()
+        };
+        state.toggles.set("powersave", false);
+        state.save(&path).unwrap();
+        let loaded = PersistentState::load(&path).unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+        assert_eq!(loaded, state);
+    }
+
+    #[test]
+    fn test_toggles() {
+        let mut toggles = Toggles::default();
+        assert_eq!(toggles.lookup(["low", "powersave"]), None);
+
+        toggles.set("powersave", false);
+        toggles.set("low", true);
+        assert_eq!(toggles.lookup(["low"]), Some(true));
+        assert_eq!(toggles.lookup(["low", "powersave"]), Some(false));
+
+        // Enabling again replaces the earlier toggle
+        toggles.set("powersave", true);
+        assert_eq!(toggles.lookup(["low", "powersave"]), Some(true));
+        assert!(toggles.disabled.is_empty());
+    }
+
+    #[test]
+    fn test_modified_tracker() {
+        let path = std::env::temp_dir().join(format!("battered-modified-{}", std::process::id()));
+        let mut tracker = ModifiedTracker::default();
+        assert!(!tracker.changed(&path)); // Missing from the start
+
+        std::fs::write(&path, "").unwrap();
+        assert!(tracker.changed(&path));
+        assert!(!tracker.changed(&path));
+
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::UNIX_EPOCH).unwrap();
+        assert!(tracker.changed(&path));
+        assert!(!tracker.changed(&path));
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(tracker.changed(&path));
+    }
+
+    #[test]
+    fn test_load_invalid_state() {
+        let result = PersistentState::load(Path::new("/dev/null/state.toml"));
+        assert!(result.is_err());
+    }
+}
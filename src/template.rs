@@ -2,6 +2,7 @@ use std::fmt::Write;
 
 pub trait Template {
     fn to_template(&self) -> String;
+    fn to_env(&self) -> Vec<(String, String)>;
 }
 
 // macro to implement the Template trait for a struct
@@ -16,12 +17,68 @@ macro_rules! impl_template {
                 )*
                 result
             }
+
+            // Transform fields into `BATTERED_<FIELD>` environment variables
+            fn to_env(&self) -> Vec<(String, String)> {
+                vec![$(
+                    (
+                        format!("BATTERED_{}", stringify!($field_name).to_uppercase()),
+                        self.$field_name.to_string(),
+                    ),
+                )*]
+            }
         }
     };
 }
 
+// Replace placeholders like `$percentage` with their values
+pub fn fill_template<T: Template>(input_string: &str, format_obj: &T) -> String {
+    let mut result = input_string.to_string();
+    let format_string = format_obj.to_template();
+
+    // Replace template vars with templated values from FormatObject
+    let mut lines: Vec<&str> = format_string.lines().collect();
+    lines.sort_by_key(|line| std::cmp::Reverse(line.find(": "))); // Longest names first
+    for line in lines {
+        let parts: Vec<&str> = line.split(": ").collect();
+        if parts.len() == 2 {
+            let placeholder = format!("${}", parts[0]);
+            result = result.replace(&placeholder, parts[1]);
+        }
+    }
+    result
+}
+
 pub struct FormatObject<'f> {
     pub percentage: &'f f32,
+    pub energy_rate: &'f f32,
+    pub energy: &'f f32,
+    pub temperature: &'f str,
+    pub health: &'f f32,
+    pub energy_full: &'f f32,
+    pub energy_full_design: &'f f32,
+    pub cycle_count: &'f str,
+    pub countdown: &'f u64,
+    pub drop: &'f f32,
+    pub drop_rate: &'f f32,
+    pub state: &'f str,
+    pub time_to_empty: &'f str,
+    pub serial: &'f str,
 }
 
-impl_template!(FormatObject { percentage });
+impl_template!(FormatObject {
+    percentage,
+    energy_rate,
+    energy,
+    temperature,
+    health,
+    energy_full,
+    energy_full_design,
+    cycle_count,
+    countdown,
+    drop,
+    drop_rate,
+    state,
+    time_to_empty,
+    serial
+});